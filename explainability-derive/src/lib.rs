@@ -0,0 +1,611 @@
+//! Procedural macros for `explainability-rs`. See the docs there, this crate is re-exported by it
+//! and isn't meant to be used on its own.
+
+use std::collections::HashMap;
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, Lit, Meta};
+
+/// Generates `<Name>Ops<'a>`, a mirror of the struct with an `&Operation` for every field, and
+/// implements `Explainable` for the struct and `ExplainedFields` for the mirror. Every field has
+/// to be a primitive number.
+#[proc_macro_derive(Explainable)]
+pub fn derive_explainable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_explainable(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_explainable(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let vis = &input.vis;
+    let ops_name = format_ident!("{name}Ops");
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "Explainable can't be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input,
+                    "Explainable needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input,
+                "Explainable can only be derived for structs",
+            ))
+        }
+    };
+
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.clone().unwrap()).collect();
+    let field_vis = fields.iter().map(|f| &f.vis);
+    let names: Vec<_> = idents.iter().map(|i| i.to_string()).collect();
+    let reasons = fields.iter().zip(&names).map(|(field, name)| {
+        let docs: Vec<String> = field
+            .attrs
+            .iter()
+            .filter_map(|attr| match &attr.meta {
+                Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                    Expr::Lit(lit) => match &lit.lit {
+                        Lit::Str(s) => Some(s.value().trim().to_owned()),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter(|line| !line.is_empty())
+            .collect();
+        if docs.is_empty() {
+            name.clone()
+        } else {
+            docs.join(" ")
+        }
+    });
+    let doc = format!("`{name}` with an operation in place of every field, see `Explainable`");
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Clone, Copy)]
+        #vis struct #ops_name<'a> {
+            #(#field_vis #idents: &'a ::explainability_rs::Operation<'a>,)*
+        }
+
+        impl ::explainability_rs::Explainable for #name {
+            type Ops<'a> = #ops_name<'a>;
+
+            fn sources<'a>(&self, ctx: &mut ::explainability_rs::Context<'a>) -> #ops_name<'a> {
+                #ops_name {
+                    #(#idents: ctx.input(
+                        #names,
+                        self.#idents as ::explainability_rs::Num,
+                        #reasons,
+                    ),)*
+                }
+            }
+        }
+
+        impl<'a> ::explainability_rs::ExplainedFields<'a> for #ops_name<'a> {
+            type Values = #name;
+
+            fn roots(&self) -> ::std::vec::Vec<(&'static str, &'a ::explainability_rs::Operation<'a>)> {
+                ::std::vec![#((#names, self.#idents),)*]
+            }
+
+            fn values(&self) -> #name {
+                #name {
+                    #(#idents: self.#idents.value() as _,)*
+                }
+            }
+        }
+    })
+}
+
+/// Rewrites a plain `fn(f32/f64, ...) -> f32/f64` so its float arithmetic runs over
+/// `&Operation`s in an arena, which becomes the function's new first argument. That argument's
+/// name is hygienic, so it can't clash with anything called `arena` in the function.
+///
+/// * float parameters become named sources, named `<function>.<parameter>`
+/// * arithmetic with one of those, or with anything else already turned into an operation, goes
+///   through the operations, and so does arithmetic between float literals. Float literals and
+///   `as f32`/`as f64` casts in it become reasonless sources
+/// * `let x = a * b;` gives the result the reason "x", and `let x = 2.5;` makes a source with
+///   that reason. The function's tail expression gets the function's name as its reason
+/// * `x += y` and friends become `x = x + y` when `x` is an operation, and so does `-x` become
+///   `0 - x`. Float arguments to methods called on an operation, like `x.max(0.0)`, become
+///   sources
+///
+/// Anything else in the body, like integer arithmetic or methods on plain floats, is left
+/// alone. Operations only work in it as far as `&Operation` supports them, and a plain float
+/// can be brought into the graph with a cast, as in `rate * years as f64`.
+#[proc_macro_attribute]
+pub fn explained(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = proc_macro2::TokenStream::from(args);
+        return syn::Error::new_spanned(args, "#[explained] doesn't take any arguments")
+            .into_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as syn::ItemFn);
+    expand_explain(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn is_float(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(p) if p.path.is_ident("f32") || p.path.is_ident("f64"))
+}
+
+fn expand_explain(mut item: syn::ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    use syn::{parse_quote, FnArg, Pat, ReturnType};
+
+    // mixed site, so the body can't see these and they can't see the body's own names
+    let arena = syn::Ident::new("arena", proc_macro2::Span::mixed_site());
+    let ctx = syn::Ident::new("ctx", proc_macro2::Span::mixed_site());
+
+    let fn_name = item.sig.ident.to_string();
+    match &item.sig.output {
+        ReturnType::Type(_, ty) if is_float(ty) => {}
+        output => {
+            return Err(syn::Error::new_spanned(
+                output,
+                "#[explained] functions have to return f32 or f64",
+            ))
+        }
+    }
+
+    let mut preamble: Vec<syn::Stmt> = vec![];
+    let mut params = HashMap::new();
+    for arg in &mut item.sig.inputs {
+        let FnArg::Typed(arg) = arg else {
+            return Err(syn::Error::new_spanned(
+                arg,
+                "#[explained] doesn't work on methods",
+            ));
+        };
+        if !is_float(&arg.ty) {
+            continue;
+        }
+        let Pat::Ident(pat) = &mut *arg.pat else {
+            return Err(syn::Error::new_spanned(
+                &arg.pat,
+                "#[explained] needs float parameters to be plain names",
+            ));
+        };
+        // the source that shadows the parameter is what gets mutated, if anything
+        let mutability = pat.mutability.take();
+        let ident = &pat.ident;
+        let reason = ident.to_string();
+        params.insert(reason.clone(), true);
+        let name = format!("{fn_name}.{reason}");
+        preamble.push(parse_quote! {
+            let #mutability #ident = #ctx.input(
+                #name,
+                #ident as ::explainability_rs::Num,
+                #reason,
+            );
+        });
+    }
+    if !preamble.is_empty() {
+        preamble.insert(
+            0,
+            parse_quote! { let mut #ctx = ::explainability_rs::Context::new(#arena); },
+        );
+    }
+
+    let mut body = ExplainBody {
+        arena: arena.clone(),
+        scopes: vec![params],
+    };
+    let kind = body.block(&mut item.block);
+    if let Some(syn::Stmt::Expr(tail, None)) = item.block.stmts.last_mut() {
+        match kind {
+            Kind::Operation => attach_reason(tail, &fn_name),
+            Kind::Float => {
+                *tail = parse_quote!(::explainability_rs::Operation::new_with_reason(
+                    #tail as ::explainability_rs::Num,
+                    #fn_name,
+                    #arena,
+                ))
+            }
+            Kind::Other => {}
+        }
+    }
+
+    item.sig.generics.params.insert(0, parse_quote!('arena));
+    item.sig.inputs.insert(
+        0,
+        parse_quote!(#arena: &'arena ::explainability_rs::OpArena<'arena>),
+    );
+    item.sig.output = parse_quote!(-> &'arena ::explainability_rs::Operation<'arena>);
+    let stmts = std::mem::take(&mut item.block.stmts);
+    item.block.stmts = preamble;
+    item.block.stmts.extend(stmts);
+    Ok(quote!(#item))
+}
+
+/// turns `a op b` into `a op (b, reason)`, which is how the arithmetic operators take a reason
+fn attach_reason(expr: &mut Expr, reason: &str) {
+    let mut target = &mut *expr;
+    while let Expr::Paren(inner) = target {
+        target = &mut inner.expr;
+    }
+    if let Expr::Binary(binary) = target {
+        if is_arithmetic(&binary.op) {
+            let right = &binary.right;
+            *binary.right = syn::parse_quote!((#right, #reason));
+        }
+    }
+}
+
+fn is_arithmetic(op: &syn::BinOp) -> bool {
+    use syn::BinOp::*;
+    matches!(op, Add(_) | Sub(_) | Mul(_) | Div(_))
+}
+
+/// what an expression in an `#[explained]` body turned out to be
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// an `&Operation`
+    Operation,
+    /// a plain float that can become a source, like a literal or a cast
+    Float,
+    /// anything else, which gets left alone
+    Other,
+}
+
+struct ExplainBody {
+    arena: syn::Ident,
+    /// for each name in scope, whether it's bound to an operation, innermost block last
+    scopes: Vec<HashMap<String, bool>>,
+}
+
+impl ExplainBody {
+    fn is_operation(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn bind(&mut self, name: String, is_operation: bool) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, is_operation);
+        }
+    }
+
+    /// turns a plain float into a reasonless source
+    fn make_source(&self, expr: &mut Expr, kind: Kind) {
+        if kind == Kind::Float {
+            let arena = &self.arena;
+            *expr = syn::parse_quote!(::explainability_rs::Operation::new(
+                #expr as ::explainability_rs::Num,
+                #arena,
+            ));
+        }
+    }
+
+    /// rewrites the block, returning what its tail expression is
+    fn block(&mut self, block: &mut syn::Block) -> Kind {
+        self.scopes.push(HashMap::new());
+        let mut kind = Kind::Other;
+        for stmt in &mut block.stmts {
+            kind = match stmt {
+                syn::Stmt::Local(local) => {
+                    self.local(local);
+                    Kind::Other
+                }
+                syn::Stmt::Expr(expr, None) => self.expr(expr),
+                syn::Stmt::Expr(expr, Some(_)) => {
+                    self.expr(expr);
+                    Kind::Other
+                }
+                // nested items aren't part of this function's graph
+                syn::Stmt::Item(_) | syn::Stmt::Macro(_) => Kind::Other,
+            };
+        }
+        self.scopes.pop();
+        kind
+    }
+
+    fn local(&mut self, local: &mut syn::Local) {
+        use syn::{parse_quote, Pat};
+        let kind = match &mut local.init {
+            Some(init) => {
+                if let Some((_, diverge)) = &mut init.diverge {
+                    self.expr(diverge);
+                }
+                self.expr(&mut init.expr)
+            }
+            None => Kind::Other,
+        };
+        let (ident, ty) = match &local.pat {
+            Pat::Ident(pat) => (pat.ident.clone(), None),
+            Pat::Type(typed) => match &*typed.pat {
+                Pat::Ident(pat) => (pat.ident.clone(), Some(&*typed.ty)),
+                _ => return,
+            },
+            _ => return,
+        };
+        let name = ident.to_string();
+        let converts = kind != Kind::Other && ty.is_none_or(is_float);
+        self.bind(name.clone(), converts);
+        let (true, Some(init)) = (converts, &mut local.init) else {
+            return;
+        };
+        // the binding holds an operation now, whatever type it was given
+        if let Pat::Type(typed) = &local.pat {
+            local.pat = (*typed.pat).clone();
+        }
+        if kind == Kind::Operation {
+            attach_reason(&mut init.expr, &name);
+        } else {
+            let (value, arena) = (&init.expr, &self.arena);
+            *init.expr = parse_quote!(::explainability_rs::Operation::new_with_reason(
+                #value as ::explainability_rs::Num,
+                #name,
+                #arena,
+            ));
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) -> Kind {
+        use syn::{parse_quote, BinOp, UnOp};
+        match expr {
+            Expr::Lit(lit) if matches!(lit.lit, Lit::Float(_)) => Kind::Float,
+            Expr::Path(path) => match path.path.get_ident() {
+                Some(ident) if path.qself.is_none() && self.is_operation(&ident.to_string()) => {
+                    Kind::Operation
+                }
+                _ => Kind::Other,
+            },
+            Expr::Paren(paren) => self.expr(&mut paren.expr),
+            Expr::Block(block) if block.label.is_none() => self.block(&mut block.block),
+            Expr::Cast(cast) => {
+                self.expr(&mut cast.expr);
+                if is_float(&cast.ty) {
+                    Kind::Float
+                } else {
+                    Kind::Other
+                }
+            }
+            Expr::Unary(unary) if matches!(unary.op, UnOp::Neg(_)) => {
+                match self.expr(&mut unary.expr) {
+                    Kind::Operation => {
+                        let (inner, arena) = (&unary.expr, &self.arena);
+                        *expr = parse_quote!((::explainability_rs::Operation::new(0., #arena) - #inner));
+                        Kind::Operation
+                    }
+                    kind => kind,
+                }
+            }
+            Expr::Binary(binary) => {
+                let left = self.expr(&mut binary.left);
+                let right = self.expr(&mut binary.right);
+                if is_arithmetic(&binary.op) {
+                    let either = left == Kind::Operation || right == Kind::Operation;
+                    if !either && (left, right) != (Kind::Float, Kind::Float) {
+                        return Kind::Other;
+                    }
+                    self.make_source(&mut binary.left, left);
+                    self.make_source(&mut binary.right, right);
+                    return Kind::Operation;
+                }
+                let op: Option<BinOp> = match binary.op {
+                    BinOp::AddAssign(_) => Some(parse_quote!(+)),
+                    BinOp::SubAssign(_) => Some(parse_quote!(-)),
+                    BinOp::MulAssign(_) => Some(parse_quote!(*)),
+                    BinOp::DivAssign(_) => Some(parse_quote!(/)),
+                    _ => None,
+                };
+                if let (Some(op), Kind::Operation) = (op, left) {
+                    self.make_source(&mut binary.right, right);
+                    let (left, right) = (&binary.left, &binary.right);
+                    *expr = parse_quote!(#left = #left #op #right);
+                }
+                Kind::Other
+            }
+            Expr::Assign(assign) => {
+                let left = self.expr(&mut assign.left);
+                let right = self.expr(&mut assign.right);
+                if left == Kind::Operation {
+                    self.make_source(&mut assign.right, right);
+                }
+                Kind::Other
+            }
+            Expr::MethodCall(call) => {
+                let receiver = self.expr(&mut call.receiver);
+                for arg in &mut call.args {
+                    let kind = self.expr(arg);
+                    if receiver == Kind::Operation {
+                        self.make_source(arg, kind);
+                    }
+                }
+                match receiver {
+                    Kind::Operation if call.method == "value" => Kind::Float,
+                    Kind::Operation => Kind::Operation,
+                    _ => Kind::Other,
+                }
+            }
+            _ => {
+                syn::visit_mut::visit_expr_mut(self, expr);
+                Kind::Other
+            }
+        }
+    }
+}
+
+/// everything the rewrite doesn't look at itself still gets searched for expressions it does
+impl syn::visit_mut::VisitMut for ExplainBody {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        self.expr(expr);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut syn::Block) {
+        self.block(block);
+    }
+
+    fn visit_local_mut(&mut self, local: &mut syn::Local) {
+        self.local(local);
+    }
+
+    // nested functions aren't part of this one's graph
+    fn visit_item_fn_mut(&mut self, _: &mut syn::ItemFn) {}
+}
+
+/// `explain!(gross - tax)` is `gross - (tax, "gross - tax")`: the arithmetic goes through the
+/// regular operators, and the result's reason is the expression's source text. The top level of
+/// the expression has to be `+`, `-`, `*` or `/`, since that's where the reason goes.
+#[proc_macro]
+pub fn explain(input: TokenStream) -> TokenStream {
+    let expr = parse_macro_input!(input as Expr);
+    let mut target = &expr;
+    while let Expr::Paren(inner) = target {
+        target = &inner.expr;
+    }
+    match target {
+        Expr::Binary(binary) if is_arithmetic(&binary.op) => {
+            let (left, op, right) = (&binary.left, &binary.op, &binary.right);
+            let text = source_text(quote!(#expr));
+            quote!(#left #op (#right, #text)).into()
+        }
+        _ => syn::Error::new_spanned(
+            expr,
+            "explain! needs a +, -, * or / at the top level of the expression",
+        )
+        .into_compile_error()
+        .into(),
+    }
+}
+
+/// `src!(rate)` makes a source holding `rate`, with the reason "rate". The arena is whatever is
+/// called `arena` where the macro is used, and `src!(my_arena, rate)` names it explicitly.
+#[proc_macro]
+pub fn src(input: TokenStream) -> TokenStream {
+    use syn::{punctuated::Punctuated, Token};
+    let args = parse_macro_input!(input with Punctuated::<Expr, Token![,]>::parse_terminated);
+    let (arena, value) = match args.len() {
+        1 => (quote!(arena), &args[0]),
+        2 => {
+            let arena = &args[0];
+            (quote!(#arena), &args[1])
+        }
+        _ => {
+            return syn::Error::new_spanned(args, "expected src!(value) or src!(arena, value)")
+                .into_compile_error()
+                .into()
+        }
+    };
+    let text = source_text(quote!(#value));
+    quote!(::explainability_rs::Operation::new_with_reason(
+        #value as ::explainability_rs::Num,
+        #text,
+        #arena,
+    ))
+    .into()
+}
+
+/// renders tokens the way they'd usually be written, since `stringify!` puts spaces around
+/// everything (`crate :: src! (arena, rate)`)
+fn source_text(tokens: proc_macro2::TokenStream) -> String {
+    use proc_macro2::{Delimiter, Spacing, TokenTree};
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Prev {
+        Start,
+        /// something a binary operator, call or index could follow
+        Operand,
+        /// a prefix operator or a joined punctuation like the first half of `::`
+        Glued,
+    }
+
+    fn render(tokens: proc_macro2::TokenStream, out: &mut String) {
+        let mut prev = Prev::Start;
+        for token in tokens {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::Brace => ("{ ", " }"),
+                        Delimiter::None => ("", ""),
+                    };
+                    // calls, indexing and macro invocations hug what comes before them
+                    if prev == Prev::Operand && open == "{ " {
+                        out.push(' ');
+                    }
+                    out.push_str(open);
+                    render(group.stream(), out);
+                    out.push_str(close);
+                    prev = Prev::Operand;
+                }
+                TokenTree::Punct(punct) => {
+                    let c = punct.as_char();
+                    let joint = punct.spacing() == Spacing::Joint;
+                    match c {
+                        ',' | ';' => {
+                            out.push(c);
+                            out.push(' ');
+                            prev = Prev::Start;
+                        }
+                        '.' | '?' | '!' if prev == Prev::Operand => {
+                            out.push(c);
+                            prev = if c == '.' { Prev::Glued } else { Prev::Operand };
+                        }
+                        ':' => {
+                            out.push(c);
+                            prev = Prev::Glued;
+                        }
+                        _ if prev == Prev::Operand => {
+                            // binary operator, spaced out once the whole of it is written
+                            out.push(' ');
+                            out.push(c);
+                            prev = Prev::Glued;
+                            if !joint {
+                                out.push(' ');
+                                prev = Prev::Start;
+                            }
+                        }
+                        _ if prev == Prev::Glued
+                            && out.ends_with(|last: char| !last.is_alphanumeric()) =>
+                        {
+                            // second half of a joined operator like `<=`
+                            out.push(c);
+                            if !joint {
+                                out.push(' ');
+                                prev = Prev::Start;
+                            }
+                        }
+                        _ => {
+                            // prefix operators like `-x` and `&x`
+                            out.push(c);
+                            prev = Prev::Glued;
+                        }
+                    }
+                }
+                other => {
+                    if prev == Prev::Operand {
+                        out.push(' ');
+                    }
+                    out.push_str(&other.to_string());
+                    prev = Prev::Operand;
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    render(tokens, &mut out);
+    out.trim_end().to_owned()
+}
@@ -0,0 +1,450 @@
+//! How much of an output comes from each input. Three ways of splitting it up are provided:
+//!
+//! * linearized: the gradient times how far each input is from its baseline. Cheap, and exact
+//!   for sums, but it ignores interactions between inputs
+//! * Shapley values: each input's average marginal effect over every order the inputs could be
+//!   switched on from the baseline in. The contributions always add up to the change in output.
+//!   Exact for up to [`EXACT_SHAPLEY_LIMIT`] inputs, sampled beyond that
+//! * integrated gradients: the gradient averaged along the straight line from the baseline to the
+//!   actual inputs, times how far each input moved. Also adds up to the change in output, up to
+//!   the error from the number of steps
+//!
+//! All of them re-evaluate the recorded graph at other input values, the same way
+//! [`crate::Context::bind`] does, and put everything back the way it was when they're done.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{rng::SplitMix64, GraphDirection, Num, Operation, OperationType};
+
+/// Shapley values are computed exactly for at most this many inputs, which takes 2^n
+/// evaluations. More inputs than that get sampled
+pub const EXACT_SHAPLEY_LIMIT: usize = 12;
+const SHAPLEY_PERMUTATIONS: usize = 256;
+const SHAPLEY_SEED: u64 = 0x5eed;
+
+/// One input's share of an output
+#[derive(Debug, Clone, Copy)]
+pub struct Contribution<'a> {
+    pub source: &'a Operation<'a>,
+    pub amount: Num,
+}
+
+/// The result of splitting an output up among its inputs, largest contributions first
+#[derive(Debug, Clone)]
+pub struct Attribution<'a> {
+    pub root: &'a Operation<'a>,
+    /// the output with every input at its baseline
+    pub baseline_output: Num,
+    pub contributions: Vec<Contribution<'a>>,
+}
+
+pub(crate) fn source_label<'a>(op: &'a Operation<'a>) -> String {
+    match (&op.op, &op.reason) {
+        (
+            OperationType::Source {
+                name: Some(name), ..
+            },
+            _,
+        ) => name.to_string(),
+        (_, Some(reason)) => reason.to_string(),
+        _ => op.value().to_string(),
+    }
+}
+
+impl<'a> Attribution<'a> {
+    fn new(
+        root: &'a Operation<'a>,
+        baseline_output: Num,
+        inputs: &[&'a Operation<'a>],
+        amounts: Vec<Num>,
+    ) -> Self {
+        let mut contributions: Vec<_> = inputs
+            .iter()
+            .zip(amounts)
+            .map(|(&source, amount)| Contribution { source, amount })
+            .collect();
+        contributions.sort_by(|a, b| b.amount.abs().total_cmp(&a.amount.abs()));
+        Attribution {
+            root,
+            baseline_output,
+            contributions,
+        }
+    }
+
+    /// the root's graph, with every input labeled with its contribution
+    pub fn as_graphviz(&self, direction: GraphDirection) -> String {
+        let mut graph = crate::OperationGraph::from_op(self.root, direction);
+        for contribution in &self.contributions {
+            graph.annotate(
+                contribution.source,
+                format!("contributes {}", contribution.amount),
+            );
+        }
+        graph.to_graphviz()
+    }
+}
+
+impl fmt::Display for Attribution<'_> {
+    /// a table of the contributions, largest first
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let change = self.root.value() - self.baseline_output;
+        let labels: Vec<_> = self
+            .contributions
+            .iter()
+            .map(|c| source_label(c.source))
+            .collect();
+        let width = labels.iter().map(|l| l.len()).max().unwrap_or(0).max(6);
+        writeln!(
+            f,
+            "{:<width$}  {:>12}  {:>12}  {:>7}",
+            "source", "value", "contribution", "share"
+        )?;
+        for (contribution, label) in self.contributions.iter().zip(&labels) {
+            let share = if change != 0. {
+                format!("{:.1}%", 100. * contribution.amount / change)
+            } else {
+                "-".into()
+            };
+            writeln!(
+                f,
+                "{label:<width$}  {:>12}  {:>12}  {share:>7}",
+                contribution.source.value(),
+                contribution.amount,
+            )?;
+        }
+        write!(
+            f,
+            "{:<width$}  {:>12}  {:>12}",
+            "total",
+            self.root.value(),
+            change
+        )
+    }
+}
+
+impl<'a> OperationType<'a> {
+    /// d(this node) / d(each history entry), at the current values
+    pub(crate) fn local_partials(&self) -> Vec<Num> {
+        use OperationType::*;
+        let history = match self {
+            Source { .. } => return vec![],
+            node => node.history(),
+        };
+        let values: Vec<Num> = history.iter().map(|op| op.value()).collect();
+        let product_except = |skip: usize| -> Num {
+            values
+                .iter()
+                .enumerate()
+                .filter(|&(idx, _)| idx != skip)
+                .map(|(_, v)| v)
+                .product()
+        };
+        let mut partials = vec![0.; history.len()];
+        match self {
+            Source { .. } => {}
+            Sum { .. } => partials.fill(1.),
+            Difference { .. } => {
+                partials.fill(-1.);
+                partials[0] = 1.;
+            }
+            Product { .. } => {
+                for (idx, partial) in partials.iter_mut().enumerate() {
+                    *partial = product_except(idx);
+                }
+            }
+            Quotient { value, .. } => {
+                let divisor = product_except(0);
+                partials[0] = 1. / divisor;
+                for idx in 1..values.len() {
+                    partials[idx] = -value.get() / values[idx];
+                }
+            }
+            // a comparison is flat everywhere it's defined
+            Compare { .. } => {}
+            Select { taken, .. } => match taken.get() {
+                crate::Branch::Then => partials[1] = 1.,
+                crate::Branch::Otherwise => partials[2] = 1.,
+            },
+            Choose { picked, .. } => partials[picked.get()] = 1.,
+            Other { op, history, .. } => {
+                partials = op
+                    .partials(history)
+                    .unwrap_or_else(|| finite_differences(*op, history));
+            }
+        }
+        partials
+    }
+}
+
+/// central differences through `reevaluate`, nudging each history entry in place
+fn finite_differences<'a>(op: &'a dyn crate::Operator, history: &[&'a Operation<'a>]) -> Vec<Num> {
+    history
+        .iter()
+        .map(|input| {
+            let cell = input.op.value_cell();
+            let original = cell.get();
+            let step = Num::EPSILON.cbrt() * original.abs().max(1.);
+            cell.set(original + step);
+            let above = op.reevaluate(history);
+            cell.set(original - step);
+            let below = op.reevaluate(history);
+            cell.set(original);
+            (above - below) / (2. * step)
+        })
+        .collect()
+}
+
+/// The recorded graph under a root, for evaluating it at other input values
+pub(crate) struct Evaluator<'a> {
+    order: Vec<&'a Operation<'a>>,
+    inputs: Vec<&'a Operation<'a>>,
+    originals: Vec<Num>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub(crate) fn new(root: &'a Operation<'a>, inputs: &[&'a Operation<'a>]) -> Self {
+        Evaluator {
+            order: root.topological_order(),
+            inputs: inputs.to_vec(),
+            originals: inputs.iter().map(|op| op.value()).collect(),
+        }
+    }
+
+    /// the root's value with the inputs set to `values`
+    pub(crate) fn evaluate(&self, values: &[Num]) -> Num {
+        for (input, &value) in self.inputs.iter().zip(values) {
+            input.set_source_value(value);
+        }
+        for node in &self.order {
+            node.op.reevaluate();
+        }
+        self.order.last().map_or(0., |root| root.value())
+    }
+
+    /// d(root) / d(each input), at whatever values the graph currently holds
+    fn gradient(&self) -> Vec<Num> {
+        let index: HashMap<*const Operation<'a>, usize> = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(idx, &op)| (op as *const _, idx))
+            .collect();
+        let mut adjoints = vec![0.; self.order.len()];
+        if let Some(last) = adjoints.last_mut() {
+            *last = 1.;
+        }
+        for (idx, node) in self.order.iter().enumerate().rev() {
+            if adjoints[idx] == 0. || matches!(node.op, OperationType::Source { .. }) {
+                continue;
+            }
+            for (prior, partial) in node.op.history().iter().zip(node.op.local_partials()) {
+                adjoints[index[&(*prior as *const _)]] += adjoints[idx] * partial;
+            }
+        }
+        self.inputs
+            .iter()
+            .map(|&input| {
+                index
+                    .get(&(input as *const _))
+                    .map_or(0., |&idx| adjoints[idx])
+            })
+            .collect()
+    }
+}
+
+impl Drop for Evaluator<'_> {
+    /// puts the inputs back, and brings everything that depends on them back up to date, not
+    /// just what's under the root
+    fn drop(&mut self) {
+        for (input, &value) in self.inputs.iter().zip(&self.originals) {
+            input.set_source_value(value);
+        }
+        for input in &self.inputs {
+            input.propagate();
+        }
+    }
+}
+
+impl<'a> Operation<'a> {
+    /// the derivative of this with respect to every source it was computed from, at the
+    /// current values
+    pub fn gradient(&'a self) -> Vec<(&'a Operation<'a>, Num)> {
+        let sources = self.sources();
+        let gradient = Evaluator::new(self, &sources).gradient();
+        sources.into_iter().zip(gradient).collect()
+    }
+
+    /// each input's contribution as the gradient times how far it is from its baseline.
+    /// `inputs` are the sources to attribute to, with everything else held where it is, and
+    /// `baseline` has one value per input
+    ///
+    /// # Panics
+    /// if `baseline` isn't the same length as `inputs`
+    pub fn linearized_attribution(
+        &'a self,
+        inputs: &[&'a Operation<'a>],
+        baseline: &[Num],
+    ) -> Attribution<'a> {
+        assert_eq!(inputs.len(), baseline.len(), "need one baseline per input");
+        let evaluator = Evaluator::new(self, inputs);
+        let amounts = evaluator
+            .gradient()
+            .iter()
+            .zip(&evaluator.originals)
+            .zip(baseline)
+            .map(|((g, x), b)| g * (x - b))
+            .collect();
+        let baseline_output = evaluator.evaluate(baseline);
+        drop(evaluator);
+        Attribution::new(self, baseline_output, inputs, amounts)
+    }
+
+    /// each input's Shapley value, moving the inputs from `baseline` to their actual values.
+    /// Exact for up to [`EXACT_SHAPLEY_LIMIT`] inputs, sampled over a fixed number of seeded
+    /// orderings above that, see [`Operation::sampled_shapley_attribution`]
+    /// ```
+    ///# use explainability_rs::{Operation, OpArena};
+    /// let arena = OpArena::new();
+    /// let (_, op_r) = Operation::make_ctors(&arena);
+    /// let hours = op_r(40., "hours");
+    /// let wage = op_r(31., "hourly wage");
+    /// let pay = hours * (wage, "pay");
+    /// let attribution = pay.shapley_attribution(&[hours, wage], &[0., 0.]);
+    /// // with both at 0 to start with, the credit gets split evenly
+    /// assert_eq!(attribution.contributions[0].amount, 620.);
+    /// println!("{attribution}");
+    /// ```
+    ///
+    /// # Panics
+    /// if `baseline` isn't the same length as `inputs`
+    pub fn shapley_attribution(
+        &'a self,
+        inputs: &[&'a Operation<'a>],
+        baseline: &[Num],
+    ) -> Attribution<'a> {
+        if inputs.len() > EXACT_SHAPLEY_LIMIT {
+            return self.sampled_shapley_attribution(
+                inputs,
+                baseline,
+                SHAPLEY_PERMUTATIONS,
+                SHAPLEY_SEED,
+            );
+        }
+        assert_eq!(inputs.len(), baseline.len(), "need one baseline per input");
+        let evaluator = Evaluator::new(self, inputs);
+        let n = inputs.len();
+        // v[mask] is the output with the inputs in mask at their actual values
+        let outputs: Vec<f64> = (0..1usize << n)
+            .map(|mask| {
+                let values: Vec<Num> = (0..n)
+                    .map(|i| {
+                        if mask & (1 << i) != 0 {
+                            evaluator.originals[i]
+                        } else {
+                            baseline[i]
+                        }
+                    })
+                    .collect();
+                evaluator.evaluate(&values) as f64
+            })
+            .collect();
+        // weight for a coalition of size s is s! (n - s - 1)! / n!
+        let mut weights = vec![0f64; n.max(1)];
+        for (s, weight) in weights.iter_mut().enumerate() {
+            let mut w = 1. / n as f64;
+            for k in 1..=s {
+                w *= k as f64 / (n - k) as f64;
+            }
+            *weight = w;
+        }
+        let amounts = (0..n)
+            .map(|i| {
+                let bit = 1 << i;
+                (0..1usize << n)
+                    .filter(|mask| mask & bit == 0)
+                    .map(|mask| {
+                        weights[mask.count_ones() as usize] * (outputs[mask | bit] - outputs[mask])
+                    })
+                    .sum::<f64>() as Num
+            })
+            .collect();
+        drop(evaluator);
+        Attribution::new(self, outputs[0] as Num, inputs, amounts)
+    }
+
+    /// Shapley values estimated from `permutations` random orderings of the inputs, drawn with
+    /// `seed` so the same call always gives the same answer
+    ///
+    /// # Panics
+    /// if `baseline` isn't the same length as `inputs`
+    pub fn sampled_shapley_attribution(
+        &'a self,
+        inputs: &[&'a Operation<'a>],
+        baseline: &[Num],
+        permutations: usize,
+        seed: u64,
+    ) -> Attribution<'a> {
+        assert_eq!(inputs.len(), baseline.len(), "need one baseline per input");
+        let evaluator = Evaluator::new(self, inputs);
+        let mut rng = SplitMix64::new(seed);
+        let baseline_output = evaluator.evaluate(baseline);
+        let mut totals = vec![0f64; inputs.len()];
+        let mut order: Vec<usize> = (0..inputs.len()).collect();
+        for _ in 0..permutations {
+            rng.shuffle(&mut order);
+            let mut values = baseline.to_vec();
+            let mut previous = baseline_output;
+            for &i in &order {
+                values[i] = evaluator.originals[i];
+                let output = evaluator.evaluate(&values);
+                totals[i] += (output - previous) as f64;
+                previous = output;
+            }
+        }
+        let amounts = totals
+            .into_iter()
+            .map(|total| (total / permutations.max(1) as f64) as Num)
+            .collect();
+        drop(evaluator);
+        Attribution::new(self, baseline_output, inputs, amounts)
+    }
+
+    /// integrated gradients from `baseline` to the actual inputs, averaging the gradient over
+    /// `steps` points along the way
+    ///
+    /// # Panics
+    /// if `baseline` isn't the same length as `inputs`
+    pub fn integrated_gradients(
+        &'a self,
+        inputs: &[&'a Operation<'a>],
+        baseline: &[Num],
+        steps: usize,
+    ) -> Attribution<'a> {
+        assert_eq!(inputs.len(), baseline.len(), "need one baseline per input");
+        let evaluator = Evaluator::new(self, inputs);
+        let steps = steps.max(1);
+        let mut totals = vec![0f64; inputs.len()];
+        for step in 0..steps {
+            // midpoints of each step
+            let alpha = (step as Num + 0.5) / steps as Num;
+            let values: Vec<Num> = evaluator
+                .originals
+                .iter()
+                .zip(baseline)
+                .map(|(x, b)| b + alpha * (x - b))
+                .collect();
+            evaluator.evaluate(&values);
+            for (total, g) in totals.iter_mut().zip(evaluator.gradient()) {
+                *total += g as f64;
+            }
+        }
+        let amounts = totals
+            .iter()
+            .zip(evaluator.originals.iter().zip(baseline))
+            .map(|(total, (x, b))| (total / steps as f64) as Num * (x - b))
+            .collect();
+        let baseline_output = evaluator.evaluate(baseline);
+        drop(evaluator);
+        Attribution::new(self, baseline_output, inputs, amounts)
+    }
+}
@@ -0,0 +1,130 @@
+//! Reusable higher-level operations, like compound interest or a weighted average, built out of
+//! the primitive ones. The whole inner graph stays in the arena, so every analysis sees through
+//! it, but graph exports show it as one labeled node unless asked to expand it.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::{Interval, Num, OpArena, Operation, OperationType, Operator};
+
+type Build = Box<dyn for<'a> Fn(&'a OpArena<'a>, &[&'a Operation<'a>]) -> &'a Operation<'a>>;
+
+/// An [`Operator`] defined by a closure that builds its inner graph out of its inputs, given the
+/// arena to make any constants in. The node it makes has the inputs followed by the inner
+/// graph's result as its history, and takes its value from that result. Graph exports collapse
+/// it into a single node by default, see [`crate::GraphBuilder::expand_composites`].
+/// ```
+///# use explainability_rs::{Composite, GraphBuilder, Operation, OpArena, Operator};
+/// let compound = Composite::new("compound interest", Some(2), |arena, ops| {
+///     let growth = Operation::new_with_reason(1., "principal", arena) + (ops[1], "growth");
+///     ops[0] * growth * (growth, "after 2 years")
+/// });
+/// let arena = OpArena::new();
+/// let (_, op_r) = Operation::make_ctors(&arena);
+/// let balance = compound.operate(&[op_r(100., "deposit"), op_r(0.5, "rate")]);
+/// assert_eq!(balance.value(), 225.);
+///
+/// let collapsed = balance.as_graphviz_with(&GraphBuilder::new());
+/// assert!(!collapsed.contains("after 2 years"));
+/// let expanded = balance.as_graphviz_with(&GraphBuilder::new().expand_composites(true));
+/// assert!(expanded.contains("after 2 years"));
+/// ```
+pub struct Composite {
+    name: &'static str,
+    arity: Option<usize>,
+    build: Build,
+}
+
+impl fmt::Debug for Composite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Composite")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl Composite {
+    /// a composite called `name` taking `arity` inputs, or any number of them if that's `None`
+    pub fn new<F>(name: &'static str, arity: Option<usize>, build: F) -> Self
+    where
+        F: for<'a> Fn(&'a OpArena<'a>, &[&'a Operation<'a>]) -> &'a Operation<'a> + 'static,
+    {
+        Composite {
+            name,
+            arity,
+            build: Box::new(build),
+        }
+    }
+}
+
+impl Operator for Composite {
+    fn symbol(&self) -> &'static str {
+        self.name
+    }
+    fn name(&self) -> Option<&'static str> {
+        Some(self.name)
+    }
+    fn is_composite(&self) -> bool {
+        true
+    }
+    fn arity(&self) -> Option<usize> {
+        self.arity
+    }
+    /// # Panics
+    /// if `ops` is empty, since there's no arena to build the inner graph in
+    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+        let arena = ops
+            .first()
+            .expect("can't build a composite out of no inputs, there's no arena to use")
+            .arena();
+        let inner = (self.build)(arena, ops);
+        let mut history = ops.to_vec();
+        history.push(inner);
+        Operation::apply(self, &history, inner.value())
+    }
+    /// the value of the inner graph's result, which comes last. By the time a rebind gets here
+    /// it's already up to date
+    fn evaluate(&self, values: &[Num]) -> Num {
+        values.last().copied().unwrap_or(Num::NAN)
+    }
+    /// everything goes through the inner graph, so the inputs only matter by way of it
+    fn partials<'a>(&'a self, history: &[&'a Operation<'a>]) -> Option<Vec<Num>> {
+        let mut partials = vec![0.; history.len()];
+        *partials.last_mut()? = 1.;
+        Some(partials)
+    }
+    fn interval(&self, history: &[Interval]) -> Option<Interval> {
+        history.last().copied()
+    }
+}
+
+/// drops the inner graph from every composite in `json`, the nested JSON of `op`, marking them
+/// `collapsed` instead
+pub(crate) fn collapse_json<'a>(json: &mut Value, op: &'a Operation<'a>) {
+    if matches!(op.op, OperationType::Source { .. }) {
+        return;
+    }
+    let Some(variant) = json
+        .get_mut("op")
+        .and_then(|variant| variant.get_mut(op.op.kind_name()))
+    else {
+        return;
+    };
+    let mut history = op.op.history();
+    if let OperationType::Other { op: operator, .. } = &op.op {
+        if operator.is_composite() {
+            variant["collapsed"] = true.into();
+            if let Some(Value::Array(entries)) = variant.get_mut("history") {
+                entries.pop();
+            }
+            history = &history[..history.len().saturating_sub(1)];
+        }
+    }
+    if let Some(Value::Array(entries)) = variant.get_mut("history") {
+        for (entry, &prior) in entries.iter_mut().zip(history) {
+            collapse_json(entry, prior);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Named inputs on top of an [`OpArena`]. Reasons are fine as human labels, but there's no way to
+//! get back to a node from its label. A [`Context`] keeps track of its inputs by name, so they can
+//! be looked up later and rebound to new values, which re-evaluates everything built from them.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use crate::{LoadError, Num, OpArena, Operation, OperationType};
+
+/// A set of named inputs in an arena.
+/// ```
+///# use explainability_rs::{Context, OpArena};
+/// let arena = OpArena::new();
+/// let mut ctx = Context::new(&arena);
+/// let principal = ctx.input("principal", 1000., "amount borrowed");
+/// let rate = ctx.input("rate", 0.05, "annual rate");
+/// let interest = principal * rate;
+/// assert_eq!(interest.value(), 50.);
+/// ctx.bind("rate", 0.06);
+/// assert_eq!(interest.value(), 60.);
+/// ```
+pub struct Context<'a> {
+    arena: &'a OpArena<'a>,
+    inputs: BTreeMap<Cow<'a, str>, &'a Operation<'a>>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(arena: &'a OpArena<'a>) -> Self {
+        Context {
+            arena,
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    /// the arena everything in this context lives in
+    pub fn arena(&self) -> &'a OpArena<'a> {
+        self.arena
+    }
+
+    /// makes a source that can be found again with `name`. Making another input with the same
+    /// name replaces it in the context, but anything already built from the old one keeps it
+    pub fn input(
+        &mut self,
+        name: impl Into<Cow<'a, str>>,
+        value: Num,
+        reason: impl Into<Cow<'a, str>>,
+    ) -> &'a Operation<'a> {
+        let name = name.into();
+        let op = OperationType::make_source(value, Some(name.clone()));
+        let input = Operation::alloc(self.arena, op, Some(reason.into()));
+        self.inputs.insert(name, input);
+        input
+    }
+
+    /// reads every source in a JSON or TOML file (see [`crate::load_sources`]) into this
+    /// context, named by their key paths
+    pub fn load(&mut self, path: impl AsRef<std::path::Path>) -> Result<(), LoadError> {
+        let sources = crate::load_sources(path, self.arena)?;
+        self.inputs
+            .extend(sources.into_iter().map(|(name, op)| (name.into(), op)));
+        Ok(())
+    }
+
+    /// the input called `name`, if there is one
+    pub fn get(&self, name: &str) -> Option<&'a Operation<'a>> {
+        self.inputs.get(name).copied()
+    }
+
+    /// sets the input called `name` to `value`, and re-evaluates everything that was built from
+    /// it. Returns the input, or `None` if there isn't one by that name
+    pub fn bind(&self, name: &str, value: Num) -> Option<&'a Operation<'a>> {
+        let input = self.get(name)?;
+        input.set_source_value(value);
+        input.propagate();
+        Some(input)
+    }
+
+    /// all the inputs, ordered by name
+    pub fn inputs(&self) -> impl Iterator<Item = (&str, &'a Operation<'a>)> + '_ {
+        self.inputs.iter().map(|(name, &op)| (name.as_ref(), op))
+    }
+}
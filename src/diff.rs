@@ -0,0 +1,389 @@
+//! Comparing two explanation graphs, for reviewing changes to calculation code or to the data
+//! that goes into it.
+//!
+//! Nodes are matched across the graphs by what they are rather than where they live in memory:
+//! the roots are matched with each other, then each matched pair's inputs are matched by reason
+//! (or source name), and inputs without one by kind and position. Whatever's left with a reason
+//! that's unique in both graphs is matched with its namesake wherever it ended up, so moving an
+//! input around doesn't make it look removed and re-added.
+
+use std::{borrow::Cow, collections::HashMap, fmt};
+
+use dot::{Edges, GraphWalk, Labeller, Nodes};
+
+use crate::{GraphDirection, Operation, OperationGraph, OperationType};
+
+/// What happened to a node between the old graph and the new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Unchanged,
+    /// matched, but with a different value, kind or inputs
+    Changed,
+    Added,
+    Removed,
+}
+
+/// A node in either graph, along with its counterpart in the other one if it has one
+#[derive(Debug, Clone, Copy)]
+pub struct NodeDiff<'o, 'n> {
+    pub change: Change,
+    pub old: Option<&'o Operation<'o>>,
+    pub new: Option<&'n Operation<'n>>,
+}
+
+/// The result of [`diff`]: every node of both graphs, matched up where possible
+pub struct GraphDiff<'o, 'n> {
+    /// nodes of the new graph in breadth-first order from the root, then removed nodes
+    pub nodes: Vec<NodeDiff<'o, 'n>>,
+    /// (input, dependent) pairs of indices into `nodes`, and which graphs have them
+    edges: Vec<((usize, usize), EdgePresence)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EdgePresence {
+    Both,
+    OldOnly,
+    NewOnly,
+}
+
+/// what a node is called, if anything
+fn label<'a>(op: &'a Operation<'a>) -> Option<&'a str> {
+    match (&op.op, &op.reason) {
+        (
+            OperationType::Source {
+                name: Some(name), ..
+            },
+            _,
+        ) => Some(name),
+        (_, Some(reason)) => Some(reason),
+        _ => None,
+    }
+}
+
+fn same_kind(old: &Operation<'_>, new: &Operation<'_>) -> bool {
+    old.op.kind_name() == new.op.kind_name() && old.op.variant_symbol() == new.op.variant_symbol()
+}
+
+fn same_value<'o, 'n>(old: &'o Operation<'o>, new: &'n Operation<'n>) -> bool {
+    let (a, b) = (old.value(), new.value());
+    a == b || (a.is_nan() && b.is_nan())
+}
+
+fn describe<'a>(op: &'a Operation<'a>) -> String {
+    let variant = op.op.variant_symbol();
+    let variant = variant.trim();
+    match (&op.op, &op.reason) {
+        (
+            OperationType::Source {
+                name: Some(name), ..
+            },
+            _,
+        ) => format!("{name} = {}", op.value()),
+        (_, Some(reason)) if variant.is_empty() => format!("{reason} = {}", op.value()),
+        (_, Some(reason)) => format!("{reason} {variant} = {}", op.value()),
+        (_, None) if variant.is_empty() => op.value().to_string(),
+        (_, None) => format!("{variant} = {}", op.value()),
+    }
+}
+
+/// Matches up the nodes of two graphs and reports what was added, removed and changed. See the
+/// [module docs](self) for how nodes are matched
+/// ```
+///# use explainability_rs::{diff, Change, Operation, OpArena};
+/// let old_arena = OpArena::new();
+/// let (_, op_r) = Operation::make_ctors(&old_arena);
+/// let old = op_r(10., "price") * (op_r(100., "units"), "revenue");
+///
+/// let new_arena = OpArena::new();
+/// let (_, op_r) = Operation::make_ctors(&new_arena);
+/// let new = op_r(12., "price") * (op_r(100., "units"), "revenue");
+///
+/// let changes = diff(old, new);
+/// let changed: Vec<_> = changes.changed().map(|node| node.new.unwrap().value()).collect();
+/// assert_eq!(changed, [1200., 12.]);
+/// println!("{changes}");
+/// ```
+pub fn diff<'o, 'n>(old_root: &'o Operation<'o>, new_root: &'n Operation<'n>) -> GraphDiff<'o, 'n> {
+    // composites expanded, so changes inside them show up
+    let old = OperationGraph::from_roots(&[old_root], GraphDirection::DataFlow, true);
+    let new = OperationGraph::from_roots(&[new_root], GraphDirection::DataFlow, true);
+    let mut old_match: Vec<Option<usize>> = vec![None; old.nodes.len()];
+    let mut new_match: Vec<Option<usize>> = vec![None; new.nodes.len()];
+    fn pair(
+        (old_match, new_match): (&mut [Option<usize>], &mut [Option<usize>]),
+        (i, j): (usize, usize),
+        queue: &mut Vec<(usize, usize)>,
+    ) {
+        old_match[i] = Some(j);
+        new_match[j] = Some(i);
+        queue.push((i, j));
+    }
+
+    // match the roots, then work down through each matched pair's inputs
+    let mut queue = vec![];
+    let mut next = 0;
+    pair((&mut old_match, &mut new_match), (0, 0), &mut queue);
+    loop {
+        while next < queue.len() {
+            let (i, j) = queue[next];
+            next += 1;
+            let old_inputs: Vec<usize> = old.inputs[i].clone();
+            let new_inputs: Vec<usize> = new.inputs[j].clone();
+            // the same reason, preferring the same kind of node too
+            for must_be_same_kind in [true, false] {
+                for &a in &old_inputs {
+                    let Some(name) = label(old.nodes[a]).filter(|_| old_match[a].is_none()) else {
+                        continue;
+                    };
+                    let found = new_inputs.iter().copied().find(|&b| {
+                        new_match[b].is_none()
+                            && label(new.nodes[b]) == Some(name)
+                            && (!must_be_same_kind || same_kind(old.nodes[a], new.nodes[b]))
+                    });
+                    if let Some(b) = found {
+                        pair((&mut old_match, &mut new_match), (a, b), &mut queue);
+                    }
+                }
+            }
+            // nameless inputs by kind, in order
+            let mut candidates = new_inputs
+                .iter()
+                .copied()
+                .filter(|&b| label(new.nodes[b]).is_none());
+            for &a in &old_inputs {
+                if old_match[a].is_some() || label(old.nodes[a]).is_some() {
+                    continue;
+                }
+                if let Some(b) = candidates
+                    .by_ref()
+                    .find(|&b| new_match[b].is_none() && same_kind(old.nodes[a], new.nodes[b]))
+                {
+                    pair((&mut old_match, &mut new_match), (a, b), &mut queue);
+                }
+            }
+        }
+        // anything left over with a name that's unique in both graphs, wherever it is
+        let mut names: HashMap<&str, (Vec<usize>, Vec<usize>)> = HashMap::new();
+        for (a, &node) in old.nodes.iter().enumerate() {
+            if let Some(name) = label(node) {
+                names.entry(name).or_default().0.push(a);
+            }
+        }
+        for (b, &node) in new.nodes.iter().enumerate() {
+            if let Some(name) = label(node) {
+                names.entry(name).or_default().1.push(b);
+            }
+        }
+        let mut found_more = false;
+        for (olds, news) in names.values() {
+            if let ([a], [b]) = (&olds[..], &news[..]) {
+                if old_match[*a].is_none() && new_match[*b].is_none() {
+                    pair((&mut old_match, &mut new_match), (*a, *b), &mut queue);
+                    found_more = true;
+                }
+            }
+        }
+        if !found_more {
+            break;
+        }
+    }
+
+    // new nodes, in order, then whatever's only in the old graph
+    let mut nodes = Vec::with_capacity(new.nodes.len());
+    let mut new_ids = vec![0; new.nodes.len()];
+    let mut old_ids = vec![0; old.nodes.len()];
+    for (b, &node) in new.nodes.iter().enumerate() {
+        new_ids[b] = nodes.len();
+        let old_node = new_match[b].map(|a| {
+            old_ids[a] = nodes.len();
+            old.nodes[a]
+        });
+        nodes.push(NodeDiff {
+            change: Change::Added,
+            old: old_node,
+            new: Some(node),
+        });
+    }
+    for (a, &node) in old.nodes.iter().enumerate() {
+        if old_match[a].is_none() {
+            old_ids[a] = nodes.len();
+            nodes.push(NodeDiff {
+                change: Change::Removed,
+                old: Some(node),
+                new: None,
+            });
+        }
+    }
+
+    let mut edges: Vec<((usize, usize), EdgePresence)> = vec![];
+    for (b, inputs) in new.inputs.iter().enumerate() {
+        for &input in inputs {
+            edges.push(((new_ids[input], new_ids[b]), EdgePresence::NewOnly));
+        }
+    }
+    for (a, inputs) in old.inputs.iter().enumerate() {
+        for &input in inputs {
+            let edge = (old_ids[input], old_ids[a]);
+            match edges.iter_mut().find(|(e, _)| *e == edge) {
+                Some((_, presence)) => *presence = EdgePresence::Both,
+                None => edges.push((edge, EdgePresence::OldOnly)),
+            }
+        }
+    }
+    edges.sort_by_key(|&(edge, _)| edge);
+    edges.dedup_by_key(|&mut (edge, _)| edge);
+
+    for (b, &b_node) in new.nodes.iter().enumerate() {
+        let Some(a) = new_match[b] else { continue };
+        let a_node = old.nodes[a];
+        let old_inputs: Vec<usize> = old.inputs[a].iter().map(|&i| old_ids[i]).collect();
+        let new_inputs: Vec<usize> = new.inputs[b].iter().map(|&i| new_ids[i]).collect();
+        nodes[new_ids[b]].change = if same_kind(a_node, b_node)
+            && same_value(a_node, b_node)
+            && old_inputs == new_inputs
+        {
+            Change::Unchanged
+        } else {
+            Change::Changed
+        };
+    }
+
+    GraphDiff { nodes, edges }
+}
+
+impl<'o, 'n> GraphDiff<'o, 'n> {
+    fn with_change(&self, change: Change) -> impl Iterator<Item = &NodeDiff<'o, 'n>> {
+        self.nodes.iter().filter(move |node| node.change == change)
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &NodeDiff<'o, 'n>> {
+        self.with_change(Change::Added)
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &NodeDiff<'o, 'n>> {
+        self.with_change(Change::Removed)
+    }
+
+    pub fn changed(&self) -> impl Iterator<Item = &NodeDiff<'o, 'n>> {
+        self.with_change(Change::Changed)
+    }
+
+    /// whether the graphs match exactly
+    pub fn is_empty(&self) -> bool {
+        self.nodes
+            .iter()
+            .all(|node| node.change == Change::Unchanged)
+    }
+
+    /// both graphs in one, in dot format: added nodes and edges in green, removed ones in red
+    /// and dashed, changed nodes in orange with their old and new values
+    pub fn to_graphviz(&self) -> String {
+        let mut writer = vec![];
+        self.write_graphviz(&mut writer)
+            .expect("writing to a Vec doesn't fail");
+        String::from_utf8(writer).expect("labels are built from Rust strings, so they're UTF-8")
+    }
+
+    /// [`GraphDiff::to_graphviz`], straight into `writer`
+    pub fn write_graphviz(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        dot::render(self, &mut writer)
+    }
+}
+
+impl fmt::Display for GraphDiff<'_, '_> {
+    /// one line per difference, `+` for added, `-` for removed and `~` for changed, then a count
+    /// of each
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            match (node.change, node.old, node.new) {
+                (Change::Added, _, Some(new)) => writeln!(f, "+ {}", describe(new))?,
+                (Change::Removed, Some(old), _) => writeln!(f, "- {}", describe(old))?,
+                (Change::Changed, Some(old), Some(new)) => {
+                    writeln!(f, "~ {} (was {})", describe(new), describe(old))?
+                }
+                _ => {}
+            }
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} changed",
+            self.added().count(),
+            self.removed().count(),
+            self.changed().count()
+        )
+    }
+}
+
+impl<'b> GraphWalk<'b, usize, (usize, usize)> for GraphDiff<'_, '_> {
+    fn nodes(&'b self) -> Nodes<'b, usize> {
+        Cow::Owned((0..self.nodes.len()).collect())
+    }
+    fn edges(&'b self) -> Edges<'b, (usize, usize)> {
+        Cow::Owned(self.edges.iter().map(|&(edge, _)| edge).collect())
+    }
+    fn source(&'b self, edge: &(usize, usize)) -> usize {
+        edge.0
+    }
+    fn target(&'b self, edge: &(usize, usize)) -> usize {
+        edge.1
+    }
+}
+
+impl<'b> Labeller<'b, usize, (usize, usize)> for GraphDiff<'_, '_> {
+    fn graph_id(&'b self) -> dot::Id<'b> {
+        dot::Id::new("diff").unwrap()
+    }
+    fn node_id(&'b self, n: &usize) -> dot::Id<'b> {
+        dot::Id::new(format!("node{n}")).unwrap()
+    }
+    fn node_label(&'b self, n: &usize) -> dot::LabelText<'b> {
+        let node = &self.nodes[*n];
+        let text = match (node.change, node.old, node.new) {
+            (Change::Changed, Some(old), Some(new)) => {
+                format!("{}\nwas {}", describe(new), describe(old))
+            }
+            (_, _, Some(new)) => describe(new),
+            (_, Some(old), None) => describe(old),
+            (_, None, None) => String::new(),
+        };
+        dot::LabelText::label(text)
+    }
+    fn node_style(&'b self, n: &usize) -> dot::Style {
+        match self.nodes[*n].change {
+            Change::Removed => dot::Style::Dashed,
+            Change::Unchanged => dot::Style::None,
+            _ => dot::Style::Bold,
+        }
+    }
+    fn node_color(&'b self, n: &usize) -> Option<dot::LabelText<'b>> {
+        match self.nodes[*n].change {
+            Change::Unchanged => None,
+            Change::Changed => Some(dot::LabelText::label("orange")),
+            Change::Added => Some(dot::LabelText::label("green")),
+            Change::Removed => Some(dot::LabelText::label("red")),
+        }
+    }
+    fn edge_style(&'b self, e: &(usize, usize)) -> dot::Style {
+        match self.presence(e) {
+            EdgePresence::OldOnly => dot::Style::Dashed,
+            EdgePresence::NewOnly => dot::Style::Bold,
+            EdgePresence::Both => dot::Style::None,
+        }
+    }
+    fn edge_color(&'b self, e: &(usize, usize)) -> Option<dot::LabelText<'b>> {
+        match self.presence(e) {
+            EdgePresence::OldOnly => Some(dot::LabelText::label("red")),
+            EdgePresence::NewOnly => Some(dot::LabelText::label("green")),
+            EdgePresence::Both => None,
+        }
+    }
+}
+
+impl GraphDiff<'_, '_> {
+    fn presence(&self, e: &(usize, usize)) -> EdgePresence {
+        self.edges
+            .iter()
+            .find(|(edge, _)| edge == e)
+            .map_or(EdgePresence::Both, |&(_, presence)| presence)
+    }
+}
@@ -0,0 +1,140 @@
+//! The crate-wide error type, for everything that can go wrong short of a bug.
+
+use std::{fmt, io};
+
+use crate::{LoadError, Num, Operation, Operator, ParseError};
+
+#[derive(Debug)]
+pub enum Error {
+    /// an operator was given the wrong number of inputs
+    Arity {
+        /// the operator's label
+        operator: String,
+        expected: usize,
+        found: usize,
+    },
+    /// an operator was given an input it can't handle
+    Domain {
+        operator: String,
+        /// which input, counting from 0
+        index: usize,
+        value: Num,
+        problem: &'static str,
+    },
+    /// an operator in a graph being rebuilt isn't in the [`crate::OperatorRegistry`]
+    UnknownOperator(String),
+    /// a registered operator couldn't be built from the parameters it was given
+    InvalidParameters {
+        /// the registered name
+        operator: String,
+        problem: String,
+    },
+    /// a node in a graph being rebuilt is malformed. `node` is its id in the JSON
+    InvalidGraph {
+        node: usize,
+        problem: String,
+    },
+    Io(io::Error),
+    Json(serde_json::Error),
+    Load(LoadError),
+    Parse(ParseError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Arity {
+                operator,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{operator}` takes {expected} input(s) but was given {found}"
+            ),
+            Error::Domain {
+                operator,
+                index,
+                value,
+                problem,
+            } => write!(
+                f,
+                "input {index} to `{operator}` is {value}, which {problem}"
+            ),
+            Error::UnknownOperator(name) => write!(f, "no operator is registered as `{name}`"),
+            Error::InvalidParameters { operator, problem } => {
+                write!(f, "can't build `{operator}`: {problem}")
+            }
+            Error::InvalidGraph { node, problem } => {
+                write!(f, "can't rebuild node {node}: {problem}")
+            }
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Json(e) => write!(f, "{e}"),
+            Error::Load(e) => write!(f, "{e}"),
+            Error::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Load(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(e: LoadError) -> Self {
+        Error::Load(e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+/// checks `ops` against the operator's arity and domain, for [`Operator::try_operate`]
+pub(crate) fn check_inputs<O: Operator + ?Sized>(
+    operator: &O,
+    ops: &[&Operation<'_>],
+) -> Result<(), Error> {
+    if let Some(expected) = operator.arity() {
+        if ops.len() != expected {
+            return Err(Error::Arity {
+                operator: operator.label().trim().to_owned(),
+                expected,
+                found: ops.len(),
+            });
+        }
+    }
+    for (index, op) in ops.iter().enumerate() {
+        let value = op.op.value();
+        operator
+            .check_input(index, value)
+            .map_err(|problem| Error::Domain {
+                operator: operator.label().trim().to_owned(),
+                index,
+                value,
+                problem,
+            })?;
+    }
+    Ok(())
+}
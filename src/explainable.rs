@@ -0,0 +1,80 @@
+//! Traits behind `#[derive(Explainable)]`, which turns a plain struct of numbers into a mirror
+//! struct of [`Operation`]s. The mirror works both ways: fill it from a value of the plain struct
+//! to get named sources, or fill it with results and collect their values (and their combined
+//! graph) back out.
+//!
+//! ```
+//! use explainability_rs::{Context, Explainable, ExplainedFields, OpArena};
+//!
+//! #[derive(Explainable)]
+//! struct TaxInput {
+//!     /// gross income for the year
+//!     income: f64,
+//!     rate: f64,
+//! }
+//!
+//! #[derive(Explainable, Debug)]
+//! struct TaxOutput {
+//!     tax: f64,
+//!     net: f64,
+//! }
+//!
+//! let arena = OpArena::new();
+//! let mut ctx = Context::new(&arena);
+//! let input = TaxInput { income: 50_000., rate: 0.2 }.sources(&mut ctx);
+//! let tax = input.income * (input.rate, "tax");
+//! let output = TaxOutputOps { tax, net: input.income - (tax, "net income") };
+//! assert_eq!(output.values().net, 40_000.);
+//! let graph = output.as_graphviz(explainability_rs::GraphDirection::DataFlow);
+//! assert!(graph.contains("gross income for the year"));
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::{Context, Error, GraphBuilder, GraphDirection, Operation};
+
+/// Implemented by `#[derive(Explainable)]` for a struct of numbers. `Ops` is the generated
+/// `<Name>Ops` struct, with an `&Operation` in place of every field
+pub trait Explainable {
+    type Ops<'a>: ExplainedFields<'a>;
+
+    /// makes a named input in `ctx` for every field. The names are the field names, and the
+    /// reasons are the fields' doc comments, falling back to the field names
+    fn sources<'a>(&self, ctx: &mut Context<'a>) -> Self::Ops<'a>;
+}
+
+/// The generated `<Name>Ops` structs, holding one operation per field
+pub trait ExplainedFields<'a> {
+    /// the plain struct these operations mirror
+    type Values;
+
+    /// every field's operation, along with the field name
+    fn roots(&self) -> Vec<(&'static str, &'a Operation<'a>)>;
+
+    /// the current value of every field
+    fn values(&self) -> Self::Values;
+
+    /// the graphs of every field as a JSON object keyed by field name
+    ///
+    /// # Panics
+    /// if serialization fails, see [`ExplainedFields::try_as_json`]
+    fn as_json(&self) -> String {
+        self.try_as_json()
+            .expect("operations always serialize to JSON")
+    }
+
+    /// [`ExplainedFields::as_json`], returning serialization errors instead of panicking
+    fn try_as_json(&self) -> Result<String, Error> {
+        let roots: BTreeMap<_, _> = self.roots().into_iter().collect();
+        Ok(serde_json::to_string_pretty(&roots)?)
+    }
+
+    /// the graphs of every field in dot format, as one graph where nodes the fields share only
+    /// show up once, and each field's node is labeled with the field's name
+    fn as_graphviz(&self, direction: GraphDirection) -> String {
+        GraphBuilder::new()
+            .direction(direction)
+            .export(&self.roots())
+            .to_graphviz()
+    }
+}
@@ -0,0 +1,77 @@
+//! Operators made from closures, for the common case where an operator is just a function of its
+//! inputs' values and doesn't need a type of its own.
+
+use std::fmt;
+
+use crate::{Num, Operation, Operator};
+
+type Function = Box<dyn Fn(&[Num]) -> Num>;
+
+/// An [`Operator`] that applies a closure to the values of its inputs. Its name is its symbol,
+/// and also its registered name, so registering a builder that makes the same closure lets an
+/// [`crate::OperatorRegistry`] rebuild graphs using it.
+/// ```
+///# use explainability_rs::{FnOperator, Operation, OpArena, Operator};
+/// let sqrt = FnOperator::from_fn("sqrt", |x| x.sqrt());
+/// let hypot = FnOperator::from_slice_fn("hypot", Some(2), |xs| xs[0].hypot(xs[1]));
+/// let arena = OpArena::new();
+/// let side = Operation::new(9., &arena);
+/// assert_eq!(sqrt.operate(&[side]).value(), 3.);
+/// assert_eq!(hypot.operate(&[side, Operation::new(12., &arena)]).value(), 15.);
+/// ```
+pub struct FnOperator {
+    name: &'static str,
+    arity: Option<usize>,
+    function: Function,
+}
+
+impl fmt::Debug for FnOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FnOperator")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl FnOperator {
+    /// an operator taking a single input
+    pub fn from_fn(name: &'static str, function: impl Fn(Num) -> Num + 'static) -> Self {
+        FnOperator {
+            name,
+            arity: Some(1),
+            function: Box::new(move |values| function(values[0])),
+        }
+    }
+
+    /// an operator taking `arity` inputs, or any number of them if that's `None`
+    pub fn from_slice_fn(
+        name: &'static str,
+        arity: Option<usize>,
+        function: impl Fn(&[Num]) -> Num + 'static,
+    ) -> Self {
+        FnOperator {
+            name,
+            arity,
+            function: Box::new(function),
+        }
+    }
+}
+
+impl Operator for FnOperator {
+    fn symbol(&self) -> &'static str {
+        self.name
+    }
+    fn name(&self) -> Option<&'static str> {
+        Some(self.name)
+    }
+    fn arity(&self) -> Option<usize> {
+        self.arity
+    }
+    fn evaluate(&self, values: &[Num]) -> Num {
+        (self.function)(values)
+    }
+    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+        Operation::evaluated(self, ops)
+    }
+}
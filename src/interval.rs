@@ -0,0 +1,361 @@
+//! Interval arithmetic over the recorded graph. Every node gets a `[lo, hi]` range that's
+//! guaranteed to hold the exact result, given ranges for the sources. Each step is computed
+//! exactly where it can be and rounded outward otherwise, so the ranges also cover the rounding
+//! error piled up along the way. For sources without bounds, that's all the range is.
+//!
+//! Two things can leave a node with no useful bounds, and they're flagged rather than silently
+//! turned into an infinite range: dividing by a range containing zero, and custom operators that
+//! don't implement [`crate::Operator::interval`].
+
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+
+use crate::{Choice, Comparison, Num, Operation, OperationType, Uncertainty};
+
+/// A closed range of numbers
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lo: Num,
+    pub hi: Num,
+}
+
+/// the error-free sum of two f64s, as the rounded sum and what rounding dropped
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    let b_part = sum - a;
+    (sum, (a - (sum - b_part)) + (b - b_part))
+}
+
+/// the largest f32 at or below `approx + err`, the exact value
+fn round_down((approx, err): (f64, f64)) -> Num {
+    let rounded = approx as Num;
+    let above = (rounded as f64) > approx || ((rounded as f64) == approx && err < 0.);
+    if above {
+        rounded.next_down()
+    } else {
+        rounded
+    }
+}
+
+/// the smallest f32 at or above `approx + err`, the exact value
+fn round_up((approx, err): (f64, f64)) -> Num {
+    let rounded = approx as Num;
+    let below = (rounded as f64) < approx || ((rounded as f64) == approx && err > 0.);
+    if below {
+        rounded.next_up()
+    } else {
+        rounded
+    }
+}
+
+/// lowest and highest of some (approximation, error) pairs, rounded outward
+fn outward(candidates: impl IntoIterator<Item = (f64, f64)>) -> Interval {
+    let mut lowest = (f64::INFINITY, 0.);
+    let mut highest = (f64::NEG_INFINITY, 0.);
+    for candidate in candidates {
+        if candidate.0.is_nan() {
+            return Interval::ENTIRE;
+        }
+        if candidate.0 < lowest.0 || (candidate.0 == lowest.0 && candidate.1 < lowest.1) {
+            lowest = candidate;
+        }
+        if candidate.0 > highest.0 || (candidate.0 == highest.0 && candidate.1 > highest.1) {
+            highest = candidate;
+        }
+    }
+    Interval {
+        lo: round_down(lowest),
+        hi: round_up(highest),
+    }
+}
+
+impl Interval {
+    /// every number
+    pub const ENTIRE: Interval = Interval {
+        lo: Num::NEG_INFINITY,
+        hi: Num::INFINITY,
+    };
+
+    /// the range between `a` and `b`, whichever way round they are
+    pub fn new(a: Num, b: Num) -> Self {
+        Interval {
+            lo: a.min(b),
+            hi: a.max(b),
+        }
+    }
+
+    /// just `value`
+    pub fn point(value: Num) -> Self {
+        Interval {
+            lo: value,
+            hi: value,
+        }
+    }
+
+    pub fn width(&self) -> Num {
+        self.hi - self.lo
+    }
+
+    pub fn contains(&self, value: Num) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    /// the smallest interval holding both
+    pub fn hull(&self, other: &Interval) -> Self {
+        Interval {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    pub fn add(&self, other: &Interval) -> Self {
+        Interval {
+            lo: round_down(two_sum(self.lo as f64, other.lo as f64)),
+            hi: round_up(two_sum(self.hi as f64, other.hi as f64)),
+        }
+    }
+
+    pub fn neg(&self) -> Self {
+        Interval {
+            lo: -self.hi,
+            hi: -self.lo,
+        }
+    }
+
+    pub fn sub(&self, other: &Interval) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Interval) -> Self {
+        // products of two f32s are exact as f64s
+        let corner = |a: Num, b: Num| {
+            if a == 0. || b == 0. {
+                (0., 0.)
+            } else {
+                (a as f64 * b as f64, 0.)
+            }
+        };
+        outward([
+            corner(self.lo, other.lo),
+            corner(self.lo, other.hi),
+            corner(self.hi, other.lo),
+            corner(self.hi, other.hi),
+        ])
+    }
+
+    /// `None` when `other` contains zero
+    pub fn div(&self, other: &Interval) -> Option<Self> {
+        if other.contains(0.) {
+            return None;
+        }
+        let corner = |a: Num, b: Num| {
+            let (a, b) = (a as f64, b as f64);
+            let quotient = a / b;
+            // the exact remainder, whose sign says which way the quotient got rounded
+            let remainder = (-quotient).mul_add(b, a);
+            (quotient, remainder / b)
+        };
+        Some(outward([
+            corner(self.lo, other.lo),
+            corner(self.lo, other.hi),
+            corner(self.hi, other.lo),
+            corner(self.hi, other.hi),
+        ]))
+    }
+
+    /// `[1, 1]` if the comparison holds everywhere, `[0, 0]` if it holds nowhere, and `[0, 1]`
+    /// if it depends on where in the ranges the values are
+    fn compare(&self, comparison: Comparison, other: &Interval) -> Self {
+        use Comparison::*;
+        let (always, never) = match comparison {
+            Greater => (self.lo > other.hi, self.hi <= other.lo),
+            GreaterOrEqual => (self.lo >= other.hi, self.hi < other.lo),
+            Less => (self.hi < other.lo, self.lo >= other.hi),
+            LessOrEqual => (self.hi <= other.lo, self.lo > other.hi),
+            Equal | NotEqual => {
+                let same_point = self.lo == self.hi && self == other;
+                let disjoint = self.hi < other.lo || other.hi < self.lo;
+                if comparison == Equal {
+                    (same_point, disjoint)
+                } else {
+                    (disjoint, same_point)
+                }
+            }
+        };
+        match (always, never) {
+            (true, _) => Interval::point(1.),
+            (_, true) => Interval::point(0.),
+            _ => Interval::new(0., 1.),
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+/// Why a node's interval isn't worth much
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntervalFlag {
+    /// a `Quotient` whose divisor's interval contains zero
+    DivisionByZero,
+    /// a custom operator without an interval implementation
+    NoIntervalImplementation,
+}
+
+impl fmt::Display for IntervalFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntervalFlag::DivisionByZero => write!(f, "divides by an interval containing 0"),
+            IntervalFlag::NoIntervalImplementation => write!(f, "operator has no interval version"),
+        }
+    }
+}
+
+/// The interval of every node under some roots, and which ones got flagged
+pub struct IntervalAnalysis<'a> {
+    intervals: HashMap<*const Operation<'a>, Interval>,
+    flags: Vec<(&'a Operation<'a>, IntervalFlag)>,
+}
+
+impl<'a> IntervalAnalysis<'a> {
+    pub(crate) fn new(roots: &[&'a Operation<'a>]) -> Self {
+        let mut analysis = IntervalAnalysis {
+            intervals: HashMap::new(),
+            flags: vec![],
+        };
+        for root in roots {
+            for node in root.topological_order() {
+                if !analysis.intervals.contains_key(&(node as *const _)) {
+                    let interval = analysis.evaluate(node);
+                    analysis.intervals.insert(node, interval);
+                }
+            }
+        }
+        analysis
+    }
+
+    /// `node`'s interval, with its history already done
+    fn evaluate(&mut self, node: &'a Operation<'a>) -> Interval {
+        use OperationType::*;
+        let history: Vec<Interval> = match &node.op {
+            Source {
+                value, uncertainty, ..
+            } => {
+                return match uncertainty.get() {
+                    Some(Uncertainty::Bounds { lo, hi }) => Interval::new(lo, hi),
+                    // a standard deviation doesn't bound anything
+                    Some(Uncertainty::StdDev(_)) => Interval::ENTIRE,
+                    None => Interval::point(value.get()),
+                };
+            }
+            op => op
+                .history()
+                .iter()
+                .map(|&prior| self.intervals[&(prior as *const _)])
+                .collect(),
+        };
+        let (first, rest) = history.split_first().expect("nodes have a history");
+        match &node.op {
+            Source { .. } => unreachable!(),
+            Sum { .. } => rest.iter().fold(*first, |acc, i| acc.add(i)),
+            Difference { .. } => rest.iter().fold(*first, |acc, i| acc.sub(i)),
+            Product { .. } => rest.iter().fold(*first, |acc, i| acc.mul(i)),
+            Quotient { .. } => {
+                let quotient = rest
+                    .iter()
+                    .try_fold(*first, |acc, divisor| acc.div(divisor));
+                quotient.unwrap_or_else(|| {
+                    self.flags.push((node, IntervalFlag::DivisionByZero));
+                    Interval::ENTIRE
+                })
+            }
+            Compare { comparison, .. } => first.compare(*comparison, &rest[0]),
+            Select { .. } => {
+                let (condition, then, otherwise) = (history[0], history[1], history[2]);
+                if !condition.contains(0.) {
+                    then
+                } else if condition == Interval::point(0.) {
+                    otherwise
+                } else {
+                    then.hull(&otherwise)
+                }
+            }
+            Choose { rule, .. } => match rule {
+                Choice::Min => Interval {
+                    lo: history.iter().map(|i| i.lo).fold(Num::INFINITY, Num::min),
+                    hi: history.iter().map(|i| i.hi).fold(Num::INFINITY, Num::min),
+                },
+                Choice::Max => Interval {
+                    lo: history
+                        .iter()
+                        .map(|i| i.lo)
+                        .fold(Num::NEG_INFINITY, Num::max),
+                    hi: history
+                        .iter()
+                        .map(|i| i.hi)
+                        .fold(Num::NEG_INFINITY, Num::max),
+                },
+                Choice::Clamp => {
+                    let (value, lo, hi) = (history[0], history[1], history[2]);
+                    Interval {
+                        lo: lo.lo.max(value.lo.min(hi.lo)),
+                        hi: lo.hi.max(value.hi.min(hi.hi)),
+                    }
+                }
+            },
+            Other { op, .. } => op.interval(&history).unwrap_or_else(|| {
+                self.flags
+                    .push((node, IntervalFlag::NoIntervalImplementation));
+                Interval::ENTIRE
+            }),
+        }
+    }
+
+    /// `op`'s interval, if it was under the roots
+    pub fn get(&self, op: &Operation<'a>) -> Option<Interval> {
+        self.intervals.get(&(op as *const _)).copied()
+    }
+
+    /// the nodes whose intervals are unbounded for a reason other than their inputs being
+    /// unbounded, in the order they were reached
+    pub fn flags(&self) -> &[(&'a Operation<'a>, IntervalFlag)] {
+        &self.flags
+    }
+
+    pub(crate) fn flag(&self, op: &Operation<'a>) -> Option<IntervalFlag> {
+        self.flags
+            .iter()
+            .find(|(flagged, _)| std::ptr::eq(*flagged, op))
+            .map(|&(_, flag)| flag)
+    }
+}
+
+impl<'a> Operation<'a> {
+    /// bounds guaranteed to hold the exact value of this, given bounds on the sources. Sources
+    /// without [`Uncertainty::Bounds`] count as exactly their value, and ones with only a
+    /// standard deviation as unbounded
+    /// ```
+    ///# use explainability_rs::{Operation, OpArena, Uncertainty};
+    /// let arena = OpArena::new();
+    /// let (op, op_r) = Operation::make_ctors(&arena);
+    /// let third = op(1.) / (op(3.), "a third");
+    /// let bounds = third.interval();
+    /// assert!((bounds.lo as f64) < 1. / 3. && 1. / 3. < bounds.hi as f64);
+    /// let rate = op_r(0.05, "rate").with_uncertainty(Uncertainty::Bounds { lo: 0.04, hi: 0.06 });
+    /// let interest = op(1000.) * (rate, "interest");
+    /// let bounds = interest.interval();
+    /// assert!((bounds.lo - 40.).abs() < 1e-3 && (bounds.hi - 60.).abs() < 1e-3);
+    /// ```
+    pub fn interval(&'a self) -> Interval {
+        IntervalAnalysis::new(&[self]).get(self).unwrap()
+    }
+
+    /// the interval of this and everything it was computed from, along with what got flagged
+    pub fn interval_analysis(&'a self) -> IntervalAnalysis<'a> {
+        IntervalAnalysis::new(&[self])
+    }
+}
@@ -7,16 +7,63 @@
 //!
 
 use derivative::Derivative;
-use serde::Serialize;
-use std::{borrow::Cow, fmt::Debug, iter::once};
-type Num = f32;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    fmt::Debug,
+    io,
+    iter::once,
+};
+pub type Num = f32;
 
+// lets the derive macros refer to `::explainability_rs` from inside this crate too
+extern crate self as explainability_rs;
+
+mod attribution;
+mod composite;
+mod context;
+mod diff;
+mod error;
+mod explainable;
+mod fn_operator;
+mod interval;
+mod loader;
 mod macros;
+mod non_finite;
+mod parser;
+mod precision;
+mod registry;
+mod rng;
+mod schedule;
+mod simulation;
 #[cfg(test)]
 mod testing;
+mod uncertainty;
 mod visualization;
+mod waterfall;
 
-use visualization::GraphDirection;
+pub use attribution::{Attribution, Contribution, EXACT_SHAPLEY_LIMIT};
+pub use composite::Composite;
+pub use context::Context;
+pub use diff::{diff, Change, GraphDiff, NodeDiff};
+pub use error::Error;
+pub use explainability_derive::{explain, explained, src, Explainable};
+pub use explainable::{Explainable, ExplainedFields};
+pub use fn_operator::FnOperator;
+pub use interval::{Interval, IntervalAnalysis, IntervalFlag};
+pub use loader::{load_sources, parse_sources, Format, LoadError, Provenance, Sources};
+pub use non_finite::{Diagnostic, NonFinite, NonFiniteCause, StrictMode};
+pub use parser::{ExpressionParser, ParseError, ParseErrorKind};
+pub use precision::{Finding, NodeError, PrecisionReport, CANCELLATION_BITS, LONG_SUM_TERMS};
+pub use registry::OperatorRegistry;
+pub use schedule::{Row, Schedule, ScheduleKind};
+pub use simulation::{Distribution, Histogram, Simulation, SimulationResult};
+pub use uncertainty::Uncertainty;
+use visualization::EdgeRole;
+pub use visualization::{GraphBuilder, GraphDirection, OperationGraph};
+pub use waterfall::{Step, Waterfall};
 
 pub(crate) type OpTuple<'a, R> = (&'a Operation<'a>, R);
 type History<'a> = Vec<&'a Operation<'a>>;
@@ -53,6 +100,14 @@ pub type OpArena<'a> = typed_arena::Arena<Operation<'a>>;
 pub struct Operation<'a> {
     op: OperationType<'a>,
     reason: Option<Cow<'a, str>>,
+    /// everything that was built with this as an input, so that rebinding a source can
+    /// re-evaluate what's downstream of it
+    #[serde(skip)]
+    #[derivative(Debug = "ignore")]
+    dependents: RefCell<History<'a>>,
+    /// the arena this node lives in, see [`Operation::arena`]. Public only so older code that
+    /// reached into it keeps building
+    #[doc(hidden)]
     #[serde(skip)]
     #[derivative(Debug = "ignore")]
     pub _allocator: &'a OpArena<'a>,
@@ -81,36 +136,456 @@ impl<'a> Operation<'a> {
         self.op.value()
     }
 
+    /// the arena this node lives in, for making constants next to it. Custom operators don't
+    /// need it, [`Operation::apply`] makes their nodes
+    pub fn arena(&self) -> &'a OpArena<'a> {
+        self._allocator
+    }
+
     pub fn new(i: Num, arena: &'a OpArena<'a>) -> &'a Self {
-        arena.alloc(Operation {
-            op: OperationType::Source { value: i },
-            reason: None,
-            _allocator: arena,
-        })
+        Operation::alloc(arena, OperationType::make_source(i, None), None)
     }
     pub fn new_with_reason(i: Num, reason: &'a str, arena: &'a OpArena<'a>) -> &'a Self {
-        arena.alloc(Operation {
-            op: OperationType::Source { value: i },
-            reason: Some(reason.into()),
+        Operation::alloc(
+            arena,
+            OperationType::make_source(i, None),
+            Some(reason.into()),
+        )
+    }
+
+    /// the node for `op` applied to `ops`, giving `value`. This is what [`Operator::operate`]
+    /// implementations return, and it lives in the same arena as the inputs.
+    /// ```
+    ///# use explainability_rs::{Num, Operation, OpArena, Operator};
+    /// #[derive(Debug)]
+    /// struct Sqrt;
+    /// impl Operator for Sqrt {
+    ///     fn symbol(&self) -> &'static str {
+    ///         "sqrt"
+    ///     }
+    ///     fn evaluate(&self, values: &[Num]) -> Num {
+    ///         values[0].sqrt()
+    ///     }
+    ///     fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+    ///         Operation::apply(self, ops, self.evaluate(&[ops[0].value()]))
+    ///     }
+    /// }
+    /// let sqrt = Sqrt;
+    /// let arena = OpArena::new();
+    /// assert_eq!(sqrt.operate(&[Operation::new(9., &arena)]).value(), 3.);
+    /// ```
+    ///
+    /// # Panics
+    /// if `ops` is empty, since there's no arena to allocate the result in
+    pub fn apply(op: &'a dyn Operator, ops: &[&'a Operation<'a>], value: Num) -> &'a Self {
+        Self::apply_inner(op, ops, value, None)
+    }
+
+    /// [`Operation::apply`], with the value [`Operator::evaluate`] gives for the values of `ops`
+    ///
+    /// # Panics
+    /// if `ops` is empty, since there's no arena to allocate the result in
+    pub fn evaluated(op: &'a dyn Operator, ops: &[&'a Operation<'a>]) -> &'a Self {
+        let values: Vec<Num> = ops.iter().map(|op| op.value()).collect();
+        Self::apply_inner(op, ops, op.evaluate(&values), None)
+    }
+
+    /// [`Operation::apply`], with a reason for the result
+    pub fn apply_with_reason(
+        op: &'a dyn Operator,
+        ops: &[&'a Operation<'a>],
+        value: Num,
+        reason: &'a str,
+    ) -> &'a Self {
+        Self::apply_inner(op, ops, value, Some(reason.into()))
+    }
+
+    fn apply_inner(
+        op: &'a dyn Operator,
+        ops: &[&'a Operation<'a>],
+        value: Num,
+        reason: Option<Cow<'a, str>>,
+    ) -> &'a Self {
+        let allocator = ops
+            .first()
+            .expect("can't apply an operator to no inputs, there's no arena to use")
+            .arena();
+        let node = OperationType::Other {
+            value: Cell::new(value),
+            op,
+            history: ops.to_vec(),
+        };
+        Operation::alloc(allocator, node, reason)
+    }
+
+    /// puts a new node in the arena, and registers it as a dependent of everything in its
+    /// history. Everything that makes an `Operation` should go through here
+    pub(crate) fn alloc(
+        arena: &'a OpArena<'a>,
+        op: OperationType<'a>,
+        reason: Option<Cow<'a, str>>,
+    ) -> &'a Self {
+        let node: &'a Self = arena.alloc(Operation {
+            op,
+            reason,
+            dependents: RefCell::default(),
             _allocator: arena,
-        })
+        });
+        if !matches!(node.op, OperationType::Source { .. }) {
+            for prior in node.op.history() {
+                prior.dependents.borrow_mut().push(node);
+            }
+        }
+        node.check_finite();
+        node
+    }
+
+    /// re-evaluates everything downstream of this node, making sure each node is recomputed
+    /// only after all of its inputs have been
+    pub(crate) fn propagate(&'a self) {
+        // post-order over the dependents, reversed, is a topological order
+        let mut post_order = vec![];
+        let mut visited = HashSet::new();
+        let mut stack = vec![(self, false)];
+        while let Some((node, finished)) = stack.pop() {
+            if finished {
+                post_order.push(node);
+            } else if visited.insert(node as *const Self) {
+                stack.push((node, true));
+                stack.extend(node.dependents.borrow().iter().map(|&d| (d, false)));
+            }
+        }
+        for node in post_order.into_iter().rev() {
+            node.op.reevaluate();
+            node.check_finite();
+        }
+    }
+
+    /// every node this one was computed from, each one before any node that uses it, and this
+    /// one last
+    pub(crate) fn topological_order(&'a self) -> Vec<&'a Self> {
+        let mut post_order = vec![];
+        let mut visited = HashSet::new();
+        let mut stack = vec![(self, false)];
+        while let Some((node, finished)) = stack.pop() {
+            if finished {
+                post_order.push(node);
+            } else if visited.insert(node as *const Self) {
+                stack.push((node, true));
+                if !matches!(node.op, OperationType::Source { .. }) {
+                    // reversed so inputs get visited in history order
+                    stack.extend(node.op.history().iter().rev().map(|&h| (h, false)));
+                }
+            }
+        }
+        post_order
+    }
+
+    /// every source this one was computed from, in the order they show up in the history
+    pub fn sources(&'a self) -> Vec<&'a Self> {
+        self.topological_order()
+            .into_iter()
+            .filter(|op| matches!(op.op, OperationType::Source { .. }))
+            .collect()
+    }
+
+    /// changes the value of a source without touching anything downstream. Does nothing to
+    /// other kinds of node
+    pub(crate) fn set_source_value(&self, value: Num) {
+        if let OperationType::Source { value: old, .. } = &self.op {
+            old.set(value);
+        }
+    }
+
+    /// where this came from, for sources read out of a file
+    pub fn provenance(&self) -> Option<&Provenance> {
+        match &self.op {
+            OperationType::Source { provenance, .. } => provenance.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// uses Serde to print the compute graph as JSON. If any of the sources are uncertain,
+    /// every node also gets its `std_dev`
+    ///
+    /// # Panics
+    /// if serialization fails, which nothing in this crate makes it do. See
+    /// [`Operation::try_as_json`]
+    pub fn as_json(&'a self) -> String {
+        self.try_as_json()
+            .expect("operations always serialize to JSON")
+    }
+
+    /// [`Operation::as_json`], returning serialization errors instead of panicking
+    pub fn try_as_json(&'a self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(&self.json_value(false)?)?)
+    }
+
+    /// [`Operation::as_json`], straight into `writer`
+    pub fn write_json(&'a self, writer: impl io::Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.json_value(false)?)?;
+        Ok(())
     }
 
-    /// uses Serde to print the compute graph as JSON
-    pub fn as_json(&self) -> String {
-        serde_json::to_string_pretty(self).unwrap()
+    /// [`Operation::as_json`], with composites expanded if `options` says to. That's the only
+    /// option the nested JSON uses, the flat JSON from [`GraphBuilder::export`] has the rest
+    pub fn as_json_with(&'a self, options: &GraphBuilder) -> String {
+        let json = self
+            .json_value(options.expand_composites)
+            .expect("operations always serialize to JSON");
+        serde_json::to_string_pretty(&json).expect("operations always serialize to JSON")
     }
 
-    /// outputs the operation and its history in dot format, which can be rendered with GraphViz
+    /// the JSON, with composites collapsed to their inputs unless `expand_composites`
+    fn json_value(
+        &'a self,
+        expand_composites: bool,
+    ) -> Result<serde_json::Value, serde_json::Error> {
+        let mut json = serde_json::to_value(self)?;
+        if let Some(propagation) = uncertainty::Propagation::new(&[self]) {
+            propagation.annotate_json(&mut json, self);
+        }
+        if !expand_composites {
+            composite::collapse_json(&mut json, self);
+        }
+        Ok(json)
+    }
+
+    /// outputs the operation and its history in dot format, which can be rendered with GraphViz.
+    /// Composites are drawn as a single node
     pub fn as_graphviz(&'a self, direction: GraphDirection) -> String {
         let graph = visualization::OperationGraph::from_op(self, direction);
         graph.to_graphviz()
     }
 
-    impl_arithmetic!(add_internal, OperationType::Sum, +, OperationType::make_sum);
-    impl_arithmetic!(sub_internal, OperationType::Difference, -, OperationType::make_difference);
-    impl_arithmetic!(div_internal, OperationType::Quotient, /, OperationType::make_quotient);
-    impl_arithmetic!(mul_internal, OperationType::Product, *, OperationType::make_product);
+    /// [`Operation::as_graphviz`], with the direction, analyses and composite expansion all
+    /// taken from `options`
+    pub fn as_graphviz_with(&'a self, options: &GraphBuilder) -> String {
+        options.graph(&[self]).to_graphviz()
+    }
+
+    /// [`Operation::as_graphviz`], straight into `writer`
+    pub fn write_graphviz(
+        &'a self,
+        direction: GraphDirection,
+        writer: impl io::Write,
+    ) -> io::Result<()> {
+        visualization::OperationGraph::from_op(self, direction).write_graphviz(writer)
+    }
+
+    /// sums everything in `ops` into a single `Sum` node carrying `reason`. Unlike folding with
+    /// `+`, this allocates one node no matter how many operands there are. Reasonless sums among
+    /// the operands get flattened into the new node, same as the `+` operator would do.
+    ///
+    /// # Panics
+    /// if `ops` is empty, since there's no arena to put the result in
+    pub fn sum_with_reason<R>(
+        ops: impl IntoIterator<Item = &'a Operation<'a>>,
+        reason: R,
+    ) -> &'a Self
+    where
+        R: Into<Cow<'a, str>>,
+    {
+        Self::flat_fold(
+            ops,
+            Some(reason.into()),
+            OperationType::make_sum,
+            0.,
+            |a, b| a + b,
+        )
+    }
+
+    /// multiplies everything in `ops` into a single `Product` node carrying `reason`. See
+    /// [`Operation::sum_with_reason`].
+    ///
+    /// # Panics
+    /// if `ops` is empty, since there's no arena to put the result in
+    pub fn product_with_reason<R>(
+        ops: impl IntoIterator<Item = &'a Operation<'a>>,
+        reason: R,
+    ) -> &'a Self
+    where
+        R: Into<Cow<'a, str>>,
+    {
+        Self::flat_fold(
+            ops,
+            Some(reason.into()),
+            OperationType::make_product,
+            1.,
+            |a, b| a * b,
+        )
+    }
+
+    fn flat_fold(
+        ops: impl IntoIterator<Item = &'a Operation<'a>>,
+        reason: Option<Cow<'a, str>>,
+        variant_ctor: fn(Num, History<'a>) -> OperationType<'a>,
+        identity: Num,
+        combine: fn(Num, Num) -> Num,
+    ) -> &'a Self {
+        let kind = std::mem::discriminant(&variant_ctor(identity, vec![]));
+        let mut value = identity;
+        let mut history = History::new();
+        for op in ops {
+            value = combine(value, op.value());
+            match &op.op {
+                // same rule as impl_arithmetic: only fold in nodes nobody bothered to explain
+                inner if op.reason.is_none() && std::mem::discriminant(inner) == kind => {
+                    history.extend(inner.history().iter().copied())
+                }
+                _ => history.push(op),
+            }
+        }
+        let allocator = history
+            .first()
+            .expect("can't fold an empty iterator of operations, there's no arena to use")
+            .arena();
+        Operation::alloc(allocator, variant_ctor(value, history), reason)
+    }
+
+    /// compares this against `other`, giving back a traceable boolean: a node holding 1 if the
+    /// comparison held and 0 if it didn't, which remembers both sides
+    pub fn compare(&'a self, other: &'a Operation<'a>, comparison: Comparison) -> &'a Self {
+        let holds = comparison.holds(self.value(), other.value());
+        let op = OperationType::Compare {
+            value: Cell::new(if holds { 1. } else { 0. }),
+            comparison,
+            history: vec![self, other],
+        };
+        Operation::alloc(self.arena(), op, None)
+    }
+
+    pub fn greater_than(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        self.compare(other, Comparison::Greater)
+    }
+    pub fn greater_or_equal(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        self.compare(other, Comparison::GreaterOrEqual)
+    }
+    pub fn less_than(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        self.compare(other, Comparison::Less)
+    }
+    pub fn less_or_equal(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        self.compare(other, Comparison::LessOrEqual)
+    }
+    pub fn equal_to(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        self.compare(other, Comparison::Equal)
+    }
+    pub fn not_equal_to(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        self.compare(other, Comparison::NotEqual)
+    }
+
+    /// picks `then` if `condition` is nonzero and `otherwise` if it's zero, like an if/else. Both
+    /// candidates and the condition are kept in the history along with which one was taken, so
+    /// the explanation shows why the value is what it is
+    /// ```
+    ///# use explainability_rs::{Operation, OpArena};
+    /// let arena = OpArena::new();
+    /// let (_, op_r) = Operation::make_ctors(&arena);
+    /// let income = op_r(50_000., "income");
+    /// let threshold = op_r(40_000., "threshold");
+    /// let rate = Operation::select(
+    ///     income.greater_than(threshold),
+    ///     op_r(0.3, "high rate"),
+    ///     op_r(0.2, "low rate"),
+    /// );
+    /// assert_eq!(rate.value(), 0.3);
+    /// ```
+    pub fn select(
+        condition: &'a Operation<'a>,
+        then: &'a Operation<'a>,
+        otherwise: &'a Operation<'a>,
+    ) -> &'a Self {
+        let taken = Branch::from_condition(condition.value());
+        let value = match taken {
+            Branch::Then => then.value(),
+            Branch::Otherwise => otherwise.value(),
+        };
+        let op = OperationType::Select {
+            value: Cell::new(value),
+            taken: Cell::new(taken),
+            history: vec![condition, then, otherwise],
+        };
+        Operation::alloc(condition.arena(), op, None)
+    }
+
+    /// the smaller of `self` and `other`, remembering which one won and by how much
+    pub fn min(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        Self::min_of([self, other])
+    }
+
+    /// the larger of `self` and `other`, remembering which one won and by how much
+    pub fn max(&'a self, other: &'a Operation<'a>) -> &'a Self {
+        Self::max_of([self, other])
+    }
+
+    /// the smallest of all the candidates, ties going to the earliest one. All candidates stay in
+    /// the history, with the winner marked
+    ///
+    /// # Panics
+    /// if `candidates` is empty
+    pub fn min_of(candidates: impl IntoIterator<Item = &'a Operation<'a>>) -> &'a Self {
+        Self::choose_extreme(candidates, Choice::Min)
+    }
+
+    /// the largest of all the candidates, ties going to the earliest one. All candidates stay in
+    /// the history, with the winner marked
+    /// ```
+    ///# use explainability_rs::{Operation, OpArena};
+    /// let arena = OpArena::new();
+    /// let (_, op_r) = Operation::make_ctors(&arena);
+    /// let standard = op_r(13_850., "standard deduction");
+    /// let itemized = op_r(12_000., "itemized deductions");
+    /// let deduction = standard.max(itemized);
+    /// assert_eq!(deduction.value(), 13_850.);
+    /// ```
+    ///
+    /// # Panics
+    /// if `candidates` is empty
+    pub fn max_of(candidates: impl IntoIterator<Item = &'a Operation<'a>>) -> &'a Self {
+        Self::choose_extreme(candidates, Choice::Max)
+    }
+
+    /// restricts this to the range `[lower, upper]`, remembering whether a bound kicked in and
+    /// by how much
+    ///
+    /// # Panics
+    /// if `lower` is greater than `upper`, same as [`f32::clamp`]
+    pub fn clamp(&'a self, lower: &'a Operation<'a>, upper: &'a Operation<'a>) -> &'a Self {
+        let (lo, hi) = (lower.value(), upper.value());
+        assert!(lo <= hi, "clamp bounds are backwards: {lo} > {hi}");
+        Self::choose(vec![self, lower, upper], Choice::Clamp)
+    }
+
+    fn choose_extreme(
+        candidates: impl IntoIterator<Item = &'a Operation<'a>>,
+        rule: Choice,
+    ) -> &'a Self {
+        let history: History<'a> = candidates.into_iter().collect();
+        assert!(
+            !history.is_empty(),
+            "can't pick from an empty set of candidates"
+        );
+        Self::choose(history, rule)
+    }
+
+    fn choose(history: History<'a>, rule: Choice) -> &'a Self {
+        let values: Vec<Num> = history.iter().map(|op| op.value()).collect();
+        let (value, picked, margin) = rule.pick(&values);
+        let allocator = history[0].arena();
+        let op = OperationType::Choose {
+            value: Cell::new(value),
+            rule,
+            picked: Cell::new(picked),
+            margin: Cell::new(margin),
+            history,
+        };
+        Operation::alloc(allocator, op, None)
+    }
+
+    impl_arithmetic!(add_internal, OperationType::Sum, +, OperationType::make_sum, true);
+    impl_arithmetic!(sub_internal, OperationType::Difference, -, OperationType::make_difference, false);
+    impl_arithmetic!(div_internal, OperationType::Quotient, /, OperationType::make_quotient, false);
+    impl_arithmetic!(mul_internal, OperationType::Product, *, OperationType::make_product, true);
 }
 
 overload_operator!(std::ops::Add, Operation::add_internal, add);
@@ -145,6 +620,22 @@ overload_operator_commented!(
     T
 );
 
+/// Sums all the operations into one flat `Sum` node. Panics on an empty iterator, since there's
+/// no arena to allocate the result in.
+impl<'a> std::iter::Sum<&'a Operation<'a>> for &'a Operation<'a> {
+    fn sum<I: Iterator<Item = &'a Operation<'a>>>(iter: I) -> Self {
+        Operation::flat_fold(iter, None, OperationType::make_sum, 0., |a, b| a + b)
+    }
+}
+
+/// Multiplies all the operations into one flat `Product` node. Panics on an empty iterator,
+/// since there's no arena to allocate the result in.
+impl<'a> std::iter::Product<&'a Operation<'a>> for &'a Operation<'a> {
+    fn product<I: Iterator<Item = &'a Operation<'a>>>(iter: I) -> Self {
+        Operation::flat_fold(iter, None, OperationType::make_product, 1., |a, b| a * b)
+    }
+}
+
 /// Custom-defined functions which may take any number of arguments. For example, you might do
 /// square root operations often, and decide to implement Operator for sqrt. This ends up being
 /// dymanically dispatched in the graph however, so benchmark things and maybe modify the crate if
@@ -152,68 +643,196 @@ overload_operator_commented!(
 pub trait Operator: Debug {
     /// How should this operator be displayed
     fn symbol(&self) -> &'static str;
-    /// What the operator does to targets. sqrt's might look something like
+    /// The name this operator is registered under in an [`OperatorRegistry`], which goes in the
+    /// JSON output so the operator can be rebuilt from it along with its
+    /// [`Operator::parameters`]. `None`, the default, means it can't be rebuilt
+    fn name(&self) -> Option<&'static str> {
+        None
+    }
+    /// Whether the last entry of the history is the result of an inner graph built from the
+    /// rest, like a [`Composite`] makes. Graph exports leave that inner graph out unless asked
+    /// to expand it
+    fn is_composite(&self) -> bool {
+        false
+    }
+    /// What to show for this operator in graphs and JSON, which can mention its parameters,
+    /// like "pow 2.5" or "round to 0.01". Defaults to `symbol`
+    fn label(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.symbol())
+    }
+    /// A longer explanation of what the operator does, for the JSON output
+    fn description(&self) -> Option<Cow<'_, str>> {
+        None
+    }
+    /// The settings that make this operator what it is, for the JSON output. None by default
+    fn parameters(&self) -> Vec<(&'static str, serde_json::Value)> {
+        vec![]
+    }
+    /// The result for inputs with these values, without making any nodes. This is what gets
+    /// used to bring a node up to date after a rebind, so it has to agree with `operate`
+    fn evaluate(&self, values: &[Num]) -> Num;
+    /// What the operator does to targets, which usually means handing them to
+    /// [`Operation::evaluated`]. sqrt's might look something like
     /// ```
-    /// use explainability_rs::{Operation};
-    /// fn operate<'a>(ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
-    ///   let ops: &[&Operation<'_>] = todo!();
-    ///   let op = ops[0];
-    ///   Operation::new(f32::sqrt(op.value()), op._allocator)
+    ///# use explainability_rs::{Num, Operation, Operator};
+    ///# #[derive(Debug)]
+    ///# struct Sqrt;
+    ///# impl Operator for Sqrt {
+    ///#     fn symbol(&self) -> &'static str {
+    ///#         "sqrt"
+    ///#     }
+    /// fn evaluate(&self, values: &[Num]) -> Num {
+    ///     values[0].sqrt()
     /// }
+    /// fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+    ///     Operation::evaluated(self, ops)
+    /// }
+    ///# }
     /// ```
-    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation;
+    /// Operators that are just a function of their inputs' values can skip implementing this
+    /// trait with [`FnOperator`]
+    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a>;
+    /// How many inputs `operate` takes. `None`, the default, means any number
+    fn arity(&self) -> Option<usize> {
+        None
+    }
+    /// Whether `value` is allowed as input number `index`, and what's wrong with it if not.
+    /// Everything is allowed by default
+    fn check_input(&self, index: usize, value: Num) -> Result<(), &'static str> {
+        let _ = (index, value);
+        Ok(())
+    }
+    /// `operate`, after checking `ops` against [`Operator::arity`] and
+    /// [`Operator::check_input`]
+    fn try_operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> Result<&'a Operation<'a>, Error> {
+        error::check_inputs(self, ops)?;
+        Ok(self.operate(ops))
+    }
+    /// Recomputes the value of a node this operator made, from its (already up to date)
+    /// history. This gets called when an input upstream is rebound through a [`Context`]. The
+    /// default hands the history's values to `evaluate`
+    fn reevaluate<'a>(&'a self, history: &[&'a Operation<'a>]) -> Num {
+        let values: Vec<Num> = history.iter().map(|op| op.value()).collect();
+        self.evaluate(&values)
+    }
+    /// The partial derivative of the result with respect to each entry of the history, at the
+    /// history's current values. These drive gradients and attribution. `None`, the default,
+    /// means they get estimated with finite differences through `reevaluate`
+    fn partials<'a>(&'a self, history: &[&'a Operation<'a>]) -> Option<Vec<Num>> {
+        let _ = history;
+        None
+    }
+    /// Bounds on the result given bounds on each entry of the history, for
+    /// [`Operation::interval`]. These should be rounded outward so they hold the exact result.
+    /// `None`, the default, leaves the result unbounded and flagged
+    fn interval(&self, history: &[Interval]) -> Option<Interval> {
+        let _ = history;
+        None
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
 pub enum OperationType<'a> {
+    /// a plain number. Sources made through a [`Context`] also have a name, which gets used as
+    /// the key for the value in the JSON output, and loaded sources know where they came from.
+    /// Any source can be given an [`Uncertainty`]
+    #[serde(serialize_with = "serialize_source")]
     Source {
-        value: Num,
+        value: Cell<Num>,
+        name: Option<Cow<'a, str>>,
+        provenance: Option<Box<Provenance>>,
+        uncertainty: Cell<Option<Uncertainty>>,
     },
     Sum {
-        value: Num,
+        value: Cell<Num>,
         history: History<'a>,
     },
     Difference {
-        value: Num,
+        value: Cell<Num>,
         history: History<'a>,
     },
     Product {
-        value: Num,
+        value: Cell<Num>,
         history: History<'a>,
     },
     Quotient {
-        value: Num,
+        value: Cell<Num>,
+        history: History<'a>,
+    },
+    /// a traceable boolean, 1 if the comparison held and 0 otherwise. History is `[lhs, rhs]`
+    Compare {
+        value: Cell<Num>,
+        comparison: Comparison,
+        history: History<'a>,
+    },
+    /// an if/else. History is `[condition, then, otherwise]`, and `taken` says which of the 2
+    /// candidates ended up as the value
+    Select {
+        value: Cell<Num>,
+        taken: Cell<Branch>,
+        history: History<'a>,
+    },
+    /// a min, max or clamp. `picked` indexes into history for the candidate that won, and
+    /// `margin` says by how much. See [`Choice`] for what the margin means for each rule
+    Choose {
+        value: Cell<Num>,
+        rule: Choice,
+        picked: Cell<usize>,
+        margin: Cell<Num>,
         history: History<'a>,
     },
     Other {
-        value: Num,
-        #[serde(skip)]
+        value: Cell<Num>,
+        #[serde(serialize_with = "serialize_operator")]
         op: &'a dyn Operator,
         history: History<'a>,
     },
 }
 
 impl<'a> OperationType<'a> {
-    fn variant_symbol(&self) -> &'static str {
+    fn variant_symbol(&self) -> Cow<'_, str> {
         use OperationType::*;
-        match self {
+        Cow::Borrowed(match self {
             Source { .. } => " ",
             Sum { .. } => " (+) ",
             Difference { .. } => " (-) ",
             Product { .. } => " (*) ",
             Quotient { .. } => " (/) ",
-            Other { op, .. } => op.symbol(),
+            Compare { comparison, .. } => comparison.symbol(),
+            Select { .. } => " (if) ",
+            Choose { rule, .. } => rule.symbol(),
+            Other { op, .. } => return Cow::Owned(format!(" {} ", op.label().trim())),
+        })
+    }
+
+    /// the name of the variant, for output formats that aren't derived by serde
+    fn kind_name(&self) -> &'static str {
+        use OperationType::*;
+        match self {
+            Source { .. } => "Source",
+            Sum { .. } => "Sum",
+            Difference { .. } => "Difference",
+            Product { .. } => "Product",
+            Quotient { .. } => "Quotient",
+            Compare { .. } => "Compare",
+            Select { .. } => "Select",
+            Choose { .. } => "Choose",
+            Other { .. } => "Other",
         }
     }
 
+    /// what this was computed from, empty for sources
     fn history(&self) -> &[&'a Operation<'a>] {
         use OperationType::*;
         match self {
-            Source { .. } => panic!("don't ask for history on a leaf"),
+            Source { .. } => &[],
             Sum { history, .. } => &history[..],
             Difference { history, .. } => &history[..],
             Product { history, .. } => &history[..],
             Quotient { history, .. } => &history[..],
+            Compare { history, .. } => &history[..],
+            Select { history, .. } => &history[..],
+            Choose { history, .. } => &history[..],
             Other { history, .. } => &history[..],
         }
     }
@@ -221,16 +840,33 @@ impl<'a> OperationType<'a> {
     fn value(&self) -> Num {
         use OperationType::*;
         match self {
-            Source { value, .. } => *value,
-            Sum { value, .. } => *value,
-            Difference { value, .. } => *value,
-            Product { value, .. } => *value,
-            Quotient { value, .. } => *value,
-            Other { value, .. } => *value,
+            Source { value, .. } => value.get(),
+            Sum { value, .. } => value.get(),
+            Difference { value, .. } => value.get(),
+            Product { value, .. } => value.get(),
+            Quotient { value, .. } => value.get(),
+            Compare { value, .. } => value.get(),
+            Select { value, .. } => value.get(),
+            Choose { value, .. } => value.get(),
+            Other { value, .. } => value.get(),
         }
     }
 
     pub fn value_mut(&mut self) -> &mut Num {
+        use OperationType::*;
+        match self {
+            Source { value, .. } => value.get_mut(),
+            Sum { value, .. } => value.get_mut(),
+            Difference { value, .. } => value.get_mut(),
+            Product { value, .. } => value.get_mut(),
+            Quotient { value, .. } => value.get_mut(),
+            Compare { value, .. } => value.get_mut(),
+            Select { value, .. } => value.get_mut(),
+            Choose { value, .. } => value.get_mut(),
+            Other { value, .. } => value.get_mut(),
+        }
+    }
+    fn value_cell(&self) -> &Cell<Num> {
         use OperationType::*;
         match self {
             Source { value, .. } => value,
@@ -238,19 +874,278 @@ impl<'a> OperationType<'a> {
             Difference { value, .. } => value,
             Product { value, .. } => value,
             Quotient { value, .. } => value,
+            Compare { value, .. } => value,
+            Select { value, .. } => value,
+            Choose { value, .. } => value,
             Other { value, .. } => value,
         }
     }
+
+    /// how the graph renderers should treat the edge from `prior` (somewhere in this node's
+    /// history) into this node
+    fn edge_role(&self, prior: &Operation<'a>) -> EdgeRole {
+        use OperationType::*;
+        match self {
+            Select { taken, history, .. } => {
+                let (then, otherwise) = (history[1], history[2]);
+                let untaken = match taken.get() {
+                    Branch::Then => otherwise,
+                    Branch::Otherwise => then,
+                };
+                // the same node can be both candidates, in which case it was taken regardless
+                if std::ptr::eq(untaken, prior) && !std::ptr::eq(then, otherwise) {
+                    EdgeRole::Untaken
+                } else {
+                    EdgeRole::Plain
+                }
+            }
+            Choose {
+                picked,
+                margin,
+                history,
+                ..
+            } if std::ptr::eq(history[picked.get()], prior) => EdgeRole::Picked {
+                margin: margin.get(),
+            },
+            _ => EdgeRole::Plain,
+        }
+    }
+
+    /// recomputes the value, and which candidate won for the variants that track that, from the
+    /// current values of the history. Sources are left alone
+    fn reevaluate(&self) {
+        use OperationType::*;
+        let values: Vec<Num> = match self {
+            Source { .. } => return,
+            node => node.history().iter().map(|op| op.value()).collect(),
+        };
+        let fold =
+            |combine: fn(Num, Num) -> Num| values[1..].iter().copied().fold(values[0], combine);
+        match self {
+            Source { .. } => {}
+            Sum { value, .. } => value.set(fold(|a, b| a + b)),
+            Difference { value, .. } => value.set(fold(|a, b| a - b)),
+            Product { value, .. } => value.set(fold(|a, b| a * b)),
+            Quotient { value, .. } => value.set(fold(|a, b| a / b)),
+            Compare {
+                value, comparison, ..
+            } => value.set(if comparison.holds(values[0], values[1]) {
+                1.
+            } else {
+                0.
+            }),
+            Select { value, taken, .. } => {
+                taken.set(Branch::from_condition(values[0]));
+                value.set(match taken.get() {
+                    Branch::Then => values[1],
+                    Branch::Otherwise => values[2],
+                });
+            }
+            Choose {
+                value,
+                rule,
+                picked,
+                margin,
+                ..
+            } => {
+                let (new_value, new_picked, new_margin) = rule.pick(&values);
+                value.set(new_value);
+                picked.set(new_picked);
+                margin.set(new_margin);
+            }
+            Other { value, op, history } => value.set(op.reevaluate(history)),
+        }
+    }
+
+    fn make_source(value: Num, name: Option<Cow<'a, str>>) -> OperationType<'a> {
+        OperationType::Source {
+            value: Cell::new(value),
+            name,
+            provenance: None,
+            uncertainty: Cell::new(None),
+        }
+    }
     fn make_sum(value: Num, history: History<'a>) -> OperationType<'a> {
-        OperationType::Sum { value, history }
+        OperationType::Sum {
+            value: Cell::new(value),
+            history,
+        }
     }
     fn make_difference(value: Num, history: History<'a>) -> OperationType<'a> {
-        OperationType::Difference { value, history }
+        OperationType::Difference {
+            value: Cell::new(value),
+            history,
+        }
     }
     fn make_product(value: Num, history: History<'a>) -> OperationType<'a> {
-        OperationType::Product { value, history }
+        OperationType::Product {
+            value: Cell::new(value),
+            history,
+        }
     }
     fn make_quotient(value: Num, history: History<'a>) -> OperationType<'a> {
-        OperationType::Quotient { value, history }
+        OperationType::Quotient {
+            value: Cell::new(value),
+            history,
+        }
+    }
+}
+
+/// named sources use their name as the key for their value, everything else uses "value"
+fn serialize_source<S: serde::Serializer>(
+    value: &Cell<Num>,
+    name: &Option<Cow<'_, str>>,
+    provenance: &Option<Box<Provenance>>,
+    uncertainty: &Cell<Option<Uncertainty>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+    let mut map = serializer.serialize_map(None)?;
+    map.serialize_entry(name.as_deref().unwrap_or("value"), &value.get())?;
+    if let Some(provenance) = provenance {
+        map.serialize_entry("provenance", provenance)?;
+    }
+    if let Some(uncertainty) = uncertainty.get() {
+        map.serialize_entry("uncertainty", &uncertainty)?;
+    }
+    map.end()
+}
+
+/// custom operators serialize as their label, along with their registered name, description and
+/// parameters if they have any, and whether they're composites
+fn serialize_operator<S: serde::Serializer>(
+    op: &&dyn Operator,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    operator_json(*op).serialize(serializer)
+}
+
+pub(crate) fn operator_json(op: &dyn Operator) -> serde_json::Value {
+    let mut json = serde_json::json!({ "label": op.label().trim() });
+    if let Some(name) = op.name() {
+        json["name"] = name.into();
+    }
+    if op.is_composite() {
+        json["composite"] = true.into();
+    }
+    if let Some(description) = op.description() {
+        json["description"] = description.into();
+    }
+    let parameters = op.parameters();
+    if !parameters.is_empty() {
+        json["parameters"] = parameters
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+    json
+}
+
+/// The relation checked by [`Operation::compare`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn symbol(self) -> &'static str {
+        use Comparison::*;
+        match self {
+            Greater => " (>) ",
+            GreaterOrEqual => " (>=) ",
+            Less => " (<) ",
+            LessOrEqual => " (<=) ",
+            Equal => " (==) ",
+            NotEqual => " (!=) ",
+        }
+    }
+
+    fn holds(self, lhs: Num, rhs: Num) -> bool {
+        use Comparison::*;
+        match self {
+            Greater => lhs > rhs,
+            GreaterOrEqual => lhs >= rhs,
+            Less => lhs < rhs,
+            LessOrEqual => lhs <= rhs,
+            Equal => lhs == rhs,
+            NotEqual => lhs != rhs,
+        }
+    }
+}
+
+/// The rule a `Choose` node used to pick among its candidates
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    /// smallest candidate wins. The margin is how far below the runner-up it was
+    Min,
+    /// largest candidate wins. The margin is how far above the runner-up it was
+    Max,
+    /// candidates are `[value, lower, upper]`. The margin is how far past the bound the value
+    /// was when a bound wins, and 0 when the value is already in range
+    Clamp,
+}
+
+impl Choice {
+    fn symbol(self) -> &'static str {
+        match self {
+            Choice::Min => " (min) ",
+            Choice::Max => " (max) ",
+            Choice::Clamp => " (clamp) ",
+        }
+    }
+
+    /// the resulting value, the index of the winning candidate and the margin it won by
+    fn pick(self, values: &[Num]) -> (Num, usize, Num) {
+        if self == Choice::Clamp {
+            let (value, lo, hi) = (values[0], values[1], values[2]);
+            return if value < lo {
+                (lo, 1, lo - value)
+            } else if value > hi {
+                (hi, 2, value - hi)
+            } else {
+                (value, 0, 0.)
+            };
+        }
+        // flip the sign for min so both rules look for the biggest number
+        let sign = if self == Choice::Min { -1. } else { 1. };
+        let mut picked = 0;
+        for (idx, value) in values.iter().enumerate() {
+            if sign * value > sign * values[picked] {
+                picked = idx;
+            }
+        }
+        let runner_up = values
+            .iter()
+            .enumerate()
+            .filter(|&(idx, _)| idx != picked)
+            .map(|(_, value)| sign * value)
+            .fold(None, |best: Option<Num>, v| {
+                Some(best.map_or(v, |b| b.max(v)))
+            });
+        let margin = runner_up.map_or(0., |r| sign * values[picked] - r);
+        (values[picked], picked, margin)
+    }
+}
+
+/// Which candidate a `Select` went with
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Branch {
+    Then,
+    Otherwise,
+}
+
+impl Branch {
+    fn from_condition(condition: Num) -> Self {
+        if condition != 0. {
+            Branch::Then
+        } else {
+            Branch::Otherwise
+        }
     }
 }
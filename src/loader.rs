@@ -0,0 +1,184 @@
+//! Reads input sources out of JSON or TOML config files. Every entry carries its value and a
+//! reason, so there's no glue code calling [`Operation::new_with_reason`] field by field, and the
+//! file and key each source came from are kept as its [`Provenance`].
+//!
+//! Entries look like `{ "value": 0.05, "reason": "annual rate" }`, optionally with a `metadata`
+//! field holding anything at all, and an uncertainty given as either `"std_dev": 0.01` or
+//! `"bounds": [0.04, 0.06]`. Tables that aren't entries are groups, and their keys get
+//! joined with dots, so `[loan.rate]` in TOML ends up as the source `loan.rate`.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{OpArena, Operation, OperationType, Uncertainty};
+
+/// Where a loaded source came from
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Provenance {
+    pub file: String,
+    /// dotted path to the entry within the file
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    /// guesses the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    UnknownFormat(PathBuf),
+    /// the entry at `key` isn't a group or a `{ value, reason }` pair
+    InvalidEntry {
+        key: String,
+        problem: &'static str,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "couldn't read sources: {e}"),
+            LoadError::Json(e) => write!(f, "invalid JSON: {e}"),
+            LoadError::Toml(e) => write!(f, "invalid TOML: {e}"),
+            LoadError::UnknownFormat(path) => {
+                write!(f, "can't tell the format of {}", path.display())
+            }
+            LoadError::InvalidEntry { key, problem } => write!(f, "entry `{key}` {problem}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Json(e) => Some(e),
+            LoadError::Toml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type Sources<'a> = BTreeMap<String, &'a Operation<'a>>;
+
+/// reads the sources in the file at `path`, working out the format from its extension
+pub fn load_sources<'a>(
+    path: impl AsRef<Path>,
+    arena: &'a OpArena<'a>,
+) -> Result<Sources<'a>, LoadError> {
+    let path = path.as_ref();
+    let format = Format::from_path(path).ok_or_else(|| LoadError::UnknownFormat(path.into()))?;
+    let text = std::fs::read_to_string(path).map_err(LoadError::Io)?;
+    parse_sources(&text, format, &path.display().to_string(), arena)
+}
+
+/// reads the sources in `text`. `origin` is what gets recorded as the file in the provenance
+pub fn parse_sources<'a>(
+    text: &str,
+    format: Format,
+    origin: &str,
+    arena: &'a OpArena<'a>,
+) -> Result<Sources<'a>, LoadError> {
+    let document: Value = match format {
+        Format::Json => serde_json::from_str(text).map_err(LoadError::Json)?,
+        Format::Toml => toml::from_str(text).map_err(LoadError::Toml)?,
+    };
+    let mut sources = Sources::new();
+    match document {
+        Value::Object(entries) => {
+            for (key, entry) in entries {
+                collect(key, entry, origin, arena, &mut sources)?;
+            }
+            Ok(sources)
+        }
+        _ => Err(LoadError::InvalidEntry {
+            key: String::new(),
+            problem: "should be a table at the top level",
+        }),
+    }
+}
+
+fn collect<'a>(
+    key: String,
+    entry: Value,
+    origin: &str,
+    arena: &'a OpArena<'a>,
+    sources: &mut Sources<'a>,
+) -> Result<(), LoadError> {
+    let invalid = |key: &str, problem| LoadError::InvalidEntry {
+        key: key.into(),
+        problem,
+    };
+    let Value::Object(mut fields) = entry else {
+        return Err(invalid(&key, "should be a table with a value and a reason"));
+    };
+    if !fields.contains_key("value") {
+        for (child, entry) in fields {
+            collect(format!("{key}.{child}"), entry, origin, arena, sources)?;
+        }
+        return Ok(());
+    }
+    let value = fields["value"]
+        .as_f64()
+        .ok_or_else(|| invalid(&key, "has a value that isn't a number"))?;
+    let reason = match fields.remove("reason") {
+        Some(Value::String(reason)) => reason,
+        Some(_) => return Err(invalid(&key, "has a reason that isn't a string")),
+        None => return Err(invalid(&key, "is missing a reason")),
+    };
+    let number = |field: &Value| field.as_f64().map(|n| n as crate::Num);
+    let uncertainty = match (fields.remove("std_dev"), fields.remove("bounds")) {
+        (None, None) => None,
+        (Some(std_dev), None) => {
+            Some(Uncertainty::StdDev(number(&std_dev).ok_or_else(|| {
+                invalid(&key, "has a std_dev that isn't a number")
+            })?))
+        }
+        (None, Some(Value::Array(bounds))) => match bounds[..] {
+            [ref lo, ref hi] => match (number(lo), number(hi)) {
+                (Some(lo), Some(hi)) => Some(Uncertainty::Bounds { lo, hi }),
+                _ => return Err(invalid(&key, "has bounds that aren't numbers")),
+            },
+            _ => return Err(invalid(&key, "needs exactly two bounds")),
+        },
+        (None, Some(_)) => return Err(invalid(&key, "has bounds that aren't a list")),
+        (Some(_), Some(_)) => return Err(invalid(&key, "has both a std_dev and bounds")),
+    };
+    let provenance = Provenance {
+        file: origin.into(),
+        key: key.clone(),
+        metadata: fields.remove("metadata"),
+    };
+    let op = OperationType::Source {
+        value: (value as crate::Num).into(),
+        name: Some(key.clone().into()),
+        provenance: Some(Box::new(provenance)),
+        uncertainty: uncertainty.into(),
+    };
+    sources.insert(key, Operation::alloc(arena, op, Some(reason.into())));
+    Ok(())
+}
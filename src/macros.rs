@@ -7,89 +7,82 @@ macro_rules! match_unordered {
 
 #[macro_export]
 macro_rules! impl_arithmetic {
-    ($fname:tt, $OpVariant:path, $operator:tt, $variant_ctor:path) => {
-        fn $fname(&'a self, other: &'a $crate::Operation<'a>) -> &'a mut Self {
+    ($fname:tt, $OpVariant:path, $operator:tt, $variant_ctor:path, $commutative:literal) => {
+        /// `explicit_reason`, when given, wins over whatever reason the folding rules would keep
+        fn $fname(
+            &'a self,
+            other: &'a $crate::Operation<'a>,
+            explicit_reason: Option<Cow<'a, str>>,
+        ) -> &'a Self {
             use $crate::OperationType::Source;
-            let self_value = self.value();
-            let other_value = other.value();
-        match (self, other) {
-            // $OpVariant $operator Source
-            // happy path: we have a summed one and we fold 1 more into it, tack it on, keep the
-            // sum's reason
-            match_unordered!(
-                foldee @ $crate::Operation {
-                    op: Source { .. },
-                    ..
-                },
-                $crate::Operation {
-                    op: $OpVariant { history, .. },
-                    reason,
-                    ..
-                },
-            ) => self._allocator.alloc($crate::Operation {
-                op: $variant_ctor ( self_value $operator other_value,
-                                 Vec::from_iter(history.iter().copied().chain(once(foldee))),
-                                 ),
-                reason: reason.clone(),
-                _allocator: self._allocator,
-            }),
-            // 2 sources (just numbers) put together, no reason given, not gonna derive one
-            (
-                $crate::Operation {
-                    op: Source { value: a },
-                    ..
-                },
-                $crate::Operation {
-                    op: Source { value: b },
-                    ..
-                },
-            ) => self._allocator.alloc($crate::Operation {
-                op: $variant_ctor(a $operator b, vec![self, other]),
-                reason: None,
-                _allocator: self._allocator,
-            }),
-            // $OpVariant $operator $OpVariant, at least 1 with no reason. Fold them in and keep the chain short
-            match_unordered!(
-                $crate::Operation {
-                    op: $OpVariant {
-                        history: hist_a,
+            let value = self.value() $operator other.value();
+            let (history, reason) = match (self, other) {
+                // $OpVariant $operator Source
+                // happy path: we have a summed one and we fold 1 more into it, tack it on, keep the
+                // sum's reason. Differences and quotients only fold from the right, since
+                // 1 - (2 - 3) isn't 2 - 3 - 1
+                match_unordered!(
+                    foldee @ $crate::Operation {
+                        op: Source { .. },
                         ..
                     },
-                    reason,
-                    ..
-                },
-                $crate::Operation {
-                    op: $OpVariant {
-                        history: hist_b,
+                    chain @ $crate::Operation {
+                        op: $OpVariant { history, .. },
+                        reason,
                         ..
                     },
-                    reason: None,
-                    ..
-                }
-            ) => self._allocator.alloc($crate::Operation {
-                op: $variant_ctor(self_value $operator other_value, hist_a
-                                 .iter()
-                                 .copied()
-                                 .chain(hist_b.iter().copied())
-                                 .collect()
-                                 ),
-                reason: reason.clone(),
-                _allocator: self._allocator,
-            }),
-            // $OpVariant 2 things with reasons for each, make a new sum with no reason, listing both
-            // sources in the "history" since we're combining semantically different sums and
-            // want to preserve the history
-            ($crate::Operation { op: a, .. }, $crate::Operation { op: b, .. }) => {
-                self._allocator.alloc($crate::Operation {
-                    op: $variant_ctor(a.value() $operator b.value(),
-                    vec![self, other],
-                    ),
-                    reason: None,
-                    _allocator: self._allocator,
-                })
-            }
+                ) if $commutative || std::ptr::eq(chain, self) => (
+                    Vec::from_iter(history.iter().copied().chain(once(foldee))),
+                    reason.clone(),
+                ),
+                // 2 sources (just numbers) put together, no reason given, not gonna derive one
+                (
+                    $crate::Operation {
+                        op: Source { .. },
+                        ..
+                    },
+                    $crate::Operation {
+                        op: Source { .. },
+                        ..
+                    },
+                ) => (vec![self, other], None),
+                // $OpVariant $operator $OpVariant, at least 1 with no reason. Fold them in and keep the chain short
+                match_unordered!(
+                    $crate::Operation {
+                        op: $OpVariant {
+                            history: hist_a,
+                            ..
+                        },
+                        reason,
+                        ..
+                    },
+                    $crate::Operation {
+                        op: $OpVariant {
+                            history: hist_b,
+                            ..
+                        },
+                        reason: None,
+                        ..
+                    }
+                ) if $commutative => (
+                    hist_a
+                        .iter()
+                        .copied()
+                        .chain(hist_b.iter().copied())
+                        .collect(),
+                    reason.clone(),
+                ),
+                // $OpVariant 2 things with reasons for each, make a new sum with no reason, listing both
+                // sources in the "history" since we're combining semantically different sums and
+                // want to preserve the history
+                _ => (vec![self, other], None),
+            };
+            $crate::Operation::alloc(
+                self.arena(),
+                $variant_ctor(value, history),
+                explicit_reason.or(reason),
+            )
         }
-    }
     };
 }
 
@@ -99,7 +92,7 @@ macro_rules! overload_operator {
         impl<'a> $trait for &'a $crate::Operation<'a> {
             type Output = &'a $crate::Operation<'a>;
             fn $traitfunc(self, other: Self) -> Self::Output {
-                $func(self, other)
+                $func(self, other, None)
             }
         }
     };
@@ -115,10 +108,7 @@ macro_rules! overload_operator_commented {
             type Output = &'a $crate::Operation<'a>;
             fn $traitfunc(self, other: $crate::OpTuple<'a, $typ>) -> Self::Output {
                 let (other, reason) = other;
-                let reason = Some(reason.into());
-                let res = $func(self, other);
-                res.reason = reason;
-                res
+                $func(self, other, Some(reason.into()))
             }
         }
     };
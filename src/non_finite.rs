@@ -0,0 +1,250 @@
+//! Tracking down where a NaN or infinity came from. Non-finite values spread through everything
+//! computed from them, so by the time one shows up in a result the interesting node is usually
+//! far away. [`Operation::first_non_finite`] walks back to it after the fact, and
+//! [`StrictMode`] records one as soon as it's made.
+
+use std::{cell::RefCell, fmt};
+
+use crate::{attribution::source_label, Num, Operation, OperationType};
+
+/// Why a node came out non-finite when none of its inputs were
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonFiniteCause {
+    /// a source that was given a non-finite value
+    Source,
+    /// a `Quotient` with a zero divisor
+    DivisionByZero,
+    /// arithmetic that went past the largest `Num`
+    Overflow,
+    /// a custom operator, with its symbol
+    Operator(&'static str),
+}
+
+impl fmt::Display for NonFiniteCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NonFiniteCause::Source => write!(f, "non-finite source"),
+            NonFiniteCause::DivisionByZero => write!(f, "division by zero"),
+            NonFiniteCause::Overflow => write!(f, "overflow"),
+            NonFiniteCause::Operator(symbol) => write!(f, "operator `{}`", symbol.trim()),
+        }
+    }
+}
+
+/// whether `node` is where a non-finite value started, and if so why
+fn origin_cause(node: &Operation<'_>) -> Option<NonFiniteCause> {
+    use OperationType::*;
+    if node.op.value().is_finite() {
+        return None;
+    }
+    let history = match &node.op {
+        Source { .. } => return Some(NonFiniteCause::Source),
+        op => op.history(),
+    };
+    if history.iter().any(|prior| !prior.op.value().is_finite()) {
+        return None;
+    }
+    Some(match &node.op {
+        Quotient { .. } if history[1..].iter().any(|d| d.op.value() == 0.) => {
+            NonFiniteCause::DivisionByZero
+        }
+        Other { op, .. } => NonFiniteCause::Operator(op.symbol()),
+        _ => NonFiniteCause::Overflow,
+    })
+}
+
+/// Where a non-finite result came from
+#[derive(Debug, Clone)]
+pub struct NonFinite<'a> {
+    /// the node that first went non-finite
+    pub origin: &'a Operation<'a>,
+    pub cause: NonFiniteCause,
+    /// from `origin` to the node that was asked about, each one an input of the next
+    pub path: Vec<&'a Operation<'a>>,
+}
+
+impl fmt::Display for NonFinite<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} from {} at {}",
+            self.origin.op.value(),
+            self.cause,
+            describe(self.origin)
+        )?;
+        if self.path.len() > 1 {
+            let path: Vec<_> = self.path.iter().map(|&op| source_label(op)).collect();
+            write!(f, ", through {}", path.join(" -> "))?;
+        }
+        Ok(())
+    }
+}
+
+fn describe<'a>(op: &'a Operation<'a>) -> String {
+    let inputs = match &op.op {
+        OperationType::Source { .. } => String::new(),
+        node => {
+            let values: Vec<_> = node
+                .history()
+                .iter()
+                .map(|p| p.value().to_string())
+                .collect();
+            format!(" of [{}]", values.join(", "))
+        }
+    };
+    format!(
+        "{}{}{inputs}",
+        source_label(op),
+        op.op.variant_symbol().trim_end()
+    )
+}
+
+impl<'a> Operation<'a> {
+    /// if this is NaN or infinite, the node it started at, found by following non-finite inputs
+    /// back (the first one in history order, if there's a choice) until reaching a node whose
+    /// inputs are all finite. `None` if this is finite
+    /// ```
+    ///# use explainability_rs::{NonFiniteCause, Operation, OpArena};
+    /// let arena = OpArena::new();
+    /// let (op, op_r) = Operation::make_ctors(&arena);
+    /// let count = op_r(0., "count");
+    /// let average = op_r(120., "total") / (count, "average");
+    /// let report = average + (op(10.), "report");
+    /// let found = report.first_non_finite().unwrap();
+    /// assert!(std::ptr::eq(found.origin, average));
+    /// assert_eq!(found.cause, NonFiniteCause::DivisionByZero);
+    /// assert_eq!(found.path.len(), 2);
+    /// ```
+    pub fn first_non_finite(&'a self) -> Option<NonFinite<'a>> {
+        if self.value().is_finite() {
+            return None;
+        }
+        let mut path = vec![self];
+        let mut node = self;
+        loop {
+            if let Some(cause) = origin_cause(node) {
+                path.reverse();
+                return Some(NonFinite {
+                    origin: node,
+                    cause,
+                    path,
+                });
+            }
+            node = *node
+                .op
+                .history()
+                .iter()
+                .find(|prior| !prior.value().is_finite())?;
+            path.push(node);
+        }
+    }
+
+    /// records a diagnostic if strict mode is on and this node is where a non-finite value
+    /// started
+    pub(crate) fn check_finite(&self) {
+        STRICT.with_borrow_mut(|strict| {
+            if strict.active == 0 {
+                return;
+            }
+            if let Some(cause) = origin_cause(self) {
+                let inputs = match &self.op {
+                    OperationType::Source { .. } => vec![],
+                    op => op.history().iter().map(|p| p.op.value()).collect(),
+                };
+                strict.diagnostics.push(Diagnostic {
+                    cause,
+                    value: self.op.value(),
+                    kind: self.op.kind_name(),
+                    reason: self.reason.as_deref().map(str::to_owned),
+                    inputs,
+                });
+            }
+        });
+    }
+}
+
+/// A non-finite value caught by [`StrictMode`] as it was made
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub cause: NonFiniteCause,
+    pub value: Num,
+    /// the kind of node that was made
+    pub kind: &'static str,
+    pub reason: Option<String>,
+    /// the values of its inputs
+    pub inputs: Vec<Num>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} from {} making a {}",
+            self.value, self.cause, self.kind
+        )?;
+        if let Some(reason) = &self.reason {
+            write!(f, " \"{reason}\"")?;
+        }
+        if !self.inputs.is_empty() {
+            let inputs: Vec<_> = self.inputs.iter().map(|v| v.to_string()).collect();
+            write!(f, " of [{}]", inputs.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Strict {
+    active: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+thread_local! {
+    static STRICT: RefCell<Strict> = RefCell::default();
+}
+
+/// While one of these is alive, every node made on this thread that turns out NaN or infinite
+/// from finite inputs gets a [`Diagnostic`] recorded on the spot, while there's still context
+/// around it. So does every node that turns out that way when an input is rebound through a
+/// [`crate::Context`]. Guards can be nested, each one seeing what was recorded since it started
+/// ```
+///# use explainability_rs::{Operation, OpArena, StrictMode};
+/// let arena = OpArena::new();
+/// let (op, op_r) = Operation::make_ctors(&arena);
+/// let strict = StrictMode::enable();
+/// let ratio = op_r(1., "hits") / (op_r(0., "attempts"), "hit rate");
+/// let _ = ratio * op(100.);
+/// // only the division gets recorded, not everything downstream of it
+/// assert_eq!(strict.diagnostics().len(), 1);
+/// assert_eq!(strict.diagnostics()[0].to_string(), "inf from division by zero making a Quotient \"hit rate\" of [1, 0]");
+/// ```
+pub struct StrictMode {
+    start: usize,
+}
+
+impl StrictMode {
+    pub fn enable() -> Self {
+        STRICT.with_borrow_mut(|strict| {
+            strict.active += 1;
+            StrictMode {
+                start: strict.diagnostics.len(),
+            }
+        })
+    }
+
+    /// everything recorded since this was enabled
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        STRICT.with_borrow(|strict| strict.diagnostics[self.start..].to_vec())
+    }
+}
+
+impl Drop for StrictMode {
+    fn drop(&mut self) {
+        STRICT.with_borrow_mut(|strict| {
+            strict.active -= 1;
+            if strict.active == 0 {
+                strict.diagnostics.clear();
+            }
+        });
+    }
+}
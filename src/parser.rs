@@ -0,0 +1,378 @@
+//! Builds an [`Operation`] graph out of an infix expression like `income * rate - deduction`, so
+//! formulas can live in config files and still come with an explanation. Variables resolve to
+//! sources handed to the parser, and function calls resolve to registered [`Operator`]s.
+//!
+//! The grammar is the usual one: `+ - * /` with the usual precedence, unary minus, parentheses,
+//! number literals, and `name(arg, ...)` calls.
+
+use std::{collections::HashMap, fmt, ops::Range};
+
+use crate::{Context, Error, Num, OpArena, Operation, Operator};
+
+/// What went wrong while parsing, see [`ParseError`] for where
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    UnexpectedToken {
+        expected: &'static str,
+    },
+    UnexpectedEnd {
+        expected: &'static str,
+    },
+    InvalidNumber,
+    UnknownVariable(String),
+    UnknownFunction(String),
+    /// a function was called with a different number of arguments than it takes
+    WrongArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    /// a function that takes any number of arguments was called with none
+    NoArguments(String),
+    /// a function was given an argument it can't handle, see [`Operator::check_input`]
+    InvalidArgument {
+        function: String,
+        /// which argument, counting from 0
+        index: usize,
+        value: Num,
+        problem: &'static str,
+    },
+    /// a function's [`Operator::try_operate`] failed some other way
+    CallFailed {
+        function: String,
+        problem: String,
+    },
+}
+
+/// A parse failure, along with the byte range in the source it applies to
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Range<usize>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseErrorKind::*;
+        match &self.kind {
+            UnexpectedChar(c) => write!(f, "unexpected character {c:?}")?,
+            UnexpectedToken { expected } => write!(f, "expected {expected}")?,
+            UnexpectedEnd { expected } => write!(f, "expected {expected}, found end of input")?,
+            InvalidNumber => write!(f, "invalid number")?,
+            UnknownVariable(name) => write!(f, "unknown variable `{name}`")?,
+            UnknownFunction(name) => write!(f, "unknown function `{name}`")?,
+            WrongArgumentCount {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{function}` takes {expected} argument(s) but was given {found}"
+            )?,
+            NoArguments(name) => write!(f, "`{name}` needs at least one argument")?,
+            InvalidArgument {
+                function,
+                index,
+                value,
+                problem,
+            } => write!(
+                f,
+                "argument {index} to `{function}` is {value}, which {problem}"
+            )?,
+            CallFailed { function, problem } => write!(f, "`{function}` failed: {problem}")?,
+        }
+        write!(f, " at {}..{}", self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'s> {
+    Number(Num),
+    Ident(&'s str),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token<'_>, Range<usize>)>, ParseError> {
+    let mut tokens = vec![];
+    let bytes = src.as_bytes();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let start = idx;
+        let c = bytes[idx] as char;
+        let single = match c {
+            '+' => Some(Token::Plus),
+            '-' => Some(Token::Minus),
+            '*' => Some(Token::Star),
+            '/' => Some(Token::Slash),
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            ',' => Some(Token::Comma),
+            _ => None,
+        };
+        if let Some(token) = single {
+            idx += 1;
+            tokens.push((token, start..idx));
+        } else if c.is_ascii_whitespace() {
+            idx += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            while idx < bytes.len() && (bytes[idx].is_ascii_alphanumeric() || bytes[idx] == b'.') {
+                // exponents can carry a sign, as in 1e-3
+                let is_exponent = matches!(bytes[idx], b'e' | b'E');
+                idx += 1;
+                if is_exponent && idx < bytes.len() && matches!(bytes[idx], b'+' | b'-') {
+                    idx += 1;
+                }
+            }
+            let number = src[start..idx].parse().map_err(|_| ParseError {
+                kind: ParseErrorKind::InvalidNumber,
+                span: start..idx,
+            })?;
+            tokens.push((Token::Number(number), start..idx));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while idx < bytes.len() && (bytes[idx].is_ascii_alphanumeric() || bytes[idx] == b'_') {
+                idx += 1;
+            }
+            tokens.push((Token::Ident(&src[start..idx]), start..idx));
+        } else {
+            let c = src[start..].chars().next().unwrap_or(c);
+            return Err(ParseError {
+                kind: ParseErrorKind::UnexpectedChar(c),
+                span: start..start + c.len_utf8(),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Turns expressions into graphs in an arena, given the variables and functions they may use.
+/// ```
+///# use explainability_rs::{Context, ExpressionParser, OpArena};
+/// let arena = OpArena::new();
+/// let mut ctx = Context::new(&arena);
+/// ctx.input("income", 50_000., "gross income");
+/// ctx.input("rate", 0.2, "tax rate");
+/// ctx.input("deduction", 1_000., "flat deduction");
+/// let tax = ExpressionParser::from_context(&ctx)
+///     .parse("income * rate - deduction")
+///     .unwrap();
+/// assert_eq!(tax.value(), 9_000.);
+/// ```
+pub struct ExpressionParser<'a> {
+    arena: &'a OpArena<'a>,
+    variables: HashMap<String, &'a Operation<'a>>,
+    functions: HashMap<String, &'a dyn Operator>,
+}
+
+impl<'a> ExpressionParser<'a> {
+    pub fn new(arena: &'a OpArena<'a>) -> Self {
+        ExpressionParser {
+            arena,
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// a parser that knows every input in `ctx` as a variable
+    pub fn from_context(ctx: &Context<'a>) -> Self {
+        ctx.inputs()
+            .fold(Self::new(ctx.arena()), |parser, (name, op)| {
+                parser.variable(name, op)
+            })
+    }
+
+    /// lets expressions refer to `source` as `name`
+    pub fn variable(mut self, name: impl Into<String>, source: &'a Operation<'a>) -> Self {
+        self.variables.insert(name.into(), source);
+        self
+    }
+
+    /// lets expressions call `op` as `name(...)`, with the arguments as its operands
+    pub fn function(mut self, name: impl Into<String>, op: &'a dyn Operator) -> Self {
+        self.functions.insert(name.into(), op);
+        self
+    }
+
+    /// parses `src` into a graph. Number literals become reasonless sources, and the arithmetic
+    /// goes through the regular operators, so it folds the same way hand-written code would
+    pub fn parse(&self, src: &str) -> Result<&'a Operation<'a>, ParseError> {
+        let tokens = tokenize(src)?;
+        let mut state = ParseState {
+            parser: self,
+            tokens: &tokens,
+            position: 0,
+            end: src.len(),
+        };
+        let op = state.expression()?;
+        match state.peek() {
+            None => Ok(op),
+            Some((_, span)) => Err(ParseError {
+                kind: ParseErrorKind::UnexpectedToken {
+                    expected: "an operator or end of input",
+                },
+                span,
+            }),
+        }
+    }
+}
+
+struct ParseState<'p, 'a, 's> {
+    parser: &'p ExpressionParser<'a>,
+    tokens: &'p [(Token<'s>, Range<usize>)],
+    position: usize,
+    end: usize,
+}
+
+impl<'a, 's> ParseState<'_, 'a, 's> {
+    fn peek(&self) -> Option<(Token<'s>, Range<usize>)> {
+        self.tokens.get(self.position).cloned()
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<(Token<'s>, Range<usize>), ParseError> {
+        let token = self.peek().ok_or(ParseError {
+            kind: ParseErrorKind::UnexpectedEnd { expected },
+            span: self.end..self.end,
+        })?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, token: Token<'s>, expected: &'static str) -> Result<(), ParseError> {
+        match self.next(expected)? {
+            (found, _) if found == token => Ok(()),
+            (_, span) => Err(ParseError {
+                kind: ParseErrorKind::UnexpectedToken { expected },
+                span,
+            }),
+        }
+    }
+
+    /// term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<&'a Operation<'a>, ParseError> {
+        let mut lhs = self.term()?;
+        while let Some((token @ (Token::Plus | Token::Minus), _)) = self.peek() {
+            self.position += 1;
+            let rhs = self.term()?;
+            lhs = if token == Token::Plus {
+                lhs + rhs
+            } else {
+                lhs - rhs
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<&'a Operation<'a>, ParseError> {
+        let mut lhs = self.unary()?;
+        while let Some((token @ (Token::Star | Token::Slash), _)) = self.peek() {
+            self.position += 1;
+            let rhs = self.unary()?;
+            lhs = if token == Token::Star {
+                lhs * rhs
+            } else {
+                lhs / rhs
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// '-' unary | primary
+    fn unary(&mut self) -> Result<&'a Operation<'a>, ParseError> {
+        if let Some((Token::Minus, _)) = self.peek() {
+            self.position += 1;
+            // negative literals stay a single source, anything else becomes 0 - x
+            if let Some((Token::Number(n), _)) = self.peek() {
+                self.position += 1;
+                return Ok(Operation::new(-n, self.parser.arena));
+            }
+            let operand = self.unary()?;
+            return Ok(Operation::new(0., self.parser.arena) - operand);
+        }
+        self.primary()
+    }
+
+    /// number | ident | ident '(' args ')' | '(' expression ')'
+    fn primary(&mut self) -> Result<&'a Operation<'a>, ParseError> {
+        const EXPECTED: &str = "a number, variable, function call or '('";
+        match self.next(EXPECTED)? {
+            (Token::Number(n), _) => Ok(Operation::new(n, self.parser.arena)),
+            (Token::LParen, _) => {
+                let inner = self.expression()?;
+                self.expect(Token::RParen, "')'")?;
+                Ok(inner)
+            }
+            (Token::Ident(name), span) if matches!(self.peek(), Some((Token::LParen, _))) => {
+                let op = *self.parser.functions.get(name).ok_or_else(|| ParseError {
+                    kind: ParseErrorKind::UnknownFunction(name.to_owned()),
+                    span: span.clone(),
+                })?;
+                self.position += 1;
+                let mut args = vec![];
+                if !matches!(self.peek(), Some((Token::RParen, _))) {
+                    args.push(self.expression()?);
+                    while let Some((Token::Comma, _)) = self.peek() {
+                        self.position += 1;
+                        args.push(self.expression()?);
+                    }
+                }
+                self.expect(Token::RParen, "',' or ')'")?;
+                // the whole call, name to closing paren
+                let span = span.start..self.tokens[self.position - 1].1.end;
+                if op.arity().is_none() && args.is_empty() {
+                    // there'd be no arena to put the result in
+                    return Err(ParseError {
+                        kind: ParseErrorKind::NoArguments(name.to_owned()),
+                        span,
+                    });
+                }
+                let function = name.to_owned();
+                op.try_operate(&args).map_err(|error| {
+                    let kind = match error {
+                        Error::Arity {
+                            expected, found, ..
+                        } => ParseErrorKind::WrongArgumentCount {
+                            function,
+                            expected,
+                            found,
+                        },
+                        Error::Domain {
+                            index,
+                            value,
+                            problem,
+                            ..
+                        } => ParseErrorKind::InvalidArgument {
+                            function,
+                            index,
+                            value,
+                            problem,
+                        },
+                        error => ParseErrorKind::CallFailed {
+                            function,
+                            problem: error.to_string(),
+                        },
+                    };
+                    ParseError { kind, span }
+                })
+            }
+            (Token::Ident(name), span) => {
+                self.parser.variables.get(name).copied().ok_or(ParseError {
+                    kind: ParseErrorKind::UnknownVariable(name.to_owned()),
+                    span,
+                })
+            }
+            (_, span) => Err(ParseError {
+                kind: ParseErrorKind::UnexpectedToken { expected: EXPECTED },
+                span,
+            }),
+        }
+    }
+}
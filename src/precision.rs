@@ -0,0 +1,262 @@
+//! Floating point error diagnostics. `Num` is an `f32`, which only has 24 bits of precision, so
+//! this re-evaluates the graph in `f64` alongside it and compares. Every node gets the absolute
+//! and relative difference between the two, and two patterns get called out:
+//!
+//! * cancellation: a `Difference` of nearly equal inputs that already carried rounding error.
+//!   The leading bits cancel and what's left is mostly that error
+//! * long sums: a `Sum` of enough terms that the rounding on each addition adds up
+//!
+//! Custom operators are re-run in `f32` on the shadow values, so their own rounding isn't seen,
+//! but the error in their inputs still carries through them.
+
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+
+use crate::{
+    attribution::source_label, waterfall::escape_xml, Branch, Choice, Comparison, Num, Operation,
+    OperationType,
+};
+
+/// A `Difference` counts as catastrophic cancellation when it loses at least this many of
+/// `f32`'s 24 significant bits and its inputs weren't exact
+pub const CANCELLATION_BITS: u32 = 8;
+/// Sums of at least this many terms get checked for accumulated rounding
+pub const LONG_SUM_TERMS: usize = 8;
+/// and get flagged if the additions themselves put them off by more than this many epsilons
+const LONG_SUM_EPSILONS: f64 = 8.;
+
+/// Something about a node's rounding worth looking at
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Finding {
+    /// a `Difference` whose result is `bits_lost` bits smaller than its largest input
+    Cancellation { bits_lost: u32 },
+    /// a `Sum` of `terms` terms whose own additions put it off by `rel_error`
+    LongSum { terms: usize, rel_error: f64 },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Cancellation { bits_lost } => {
+                write!(f, "cancellation loses {bits_lost} of 24 bits")
+            }
+            Finding::LongSum { terms, rel_error } => {
+                write!(
+                    f,
+                    "sum of {terms} terms, off by {rel_error:.1e} from rounding"
+                )
+            }
+        }
+    }
+}
+
+/// One node's value next to its higher precision shadow
+#[derive(Debug, Clone, Copy)]
+pub struct NodeError<'a> {
+    pub node: &'a Operation<'a>,
+    /// the node's value evaluated in `f64` from the same sources
+    pub shadow: f64,
+    pub abs_error: f64,
+    /// `abs_error` relative to `shadow`, infinite if the shadow is 0 but the value isn't
+    pub rel_error: f64,
+    pub finding: Option<Finding>,
+}
+
+fn relative(abs_error: f64, exact: f64) -> f64 {
+    if abs_error == 0. {
+        0.
+    } else {
+        abs_error / exact.abs()
+    }
+}
+
+fn fold(values: &[f64], combine: fn(f64, f64) -> f64) -> f64 {
+    values[1..].iter().copied().fold(values[0], combine)
+}
+
+fn holds(comparison: Comparison, lhs: f64, rhs: f64) -> bool {
+    use Comparison::*;
+    match comparison {
+        Greater => lhs > rhs,
+        GreaterOrEqual => lhs >= rhs,
+        Less => lhs < rhs,
+        LessOrEqual => lhs <= rhs,
+        Equal => lhs == rhs,
+        NotEqual => lhs != rhs,
+    }
+}
+
+fn pick(rule: Choice, values: &[f64]) -> f64 {
+    match rule {
+        Choice::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+        Choice::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        Choice::Clamp => values[0].max(values[1]).min(values[2]),
+    }
+}
+
+/// The result of [`Operation::precision_report`]
+pub struct PrecisionReport<'a> {
+    /// every node, each one after its inputs
+    pub nodes: Vec<NodeError<'a>>,
+    index: HashMap<*const Operation<'a>, usize>,
+}
+
+impl<'a> PrecisionReport<'a> {
+    pub(crate) fn new(roots: &[&'a Operation<'a>]) -> Self {
+        let mut report = PrecisionReport {
+            nodes: vec![],
+            index: HashMap::new(),
+        };
+        for root in roots {
+            for node in root.topological_order() {
+                if !report.index.contains_key(&(node as *const _)) {
+                    let entry = report.evaluate(node);
+                    report.index.insert(node, report.nodes.len());
+                    report.nodes.push(entry);
+                }
+            }
+        }
+        report
+    }
+
+    fn evaluate(&self, node: &'a Operation<'a>) -> NodeError<'a> {
+        use OperationType::*;
+        let value = node.value() as f64;
+        let (shadows, inputs): (Vec<f64>, Vec<f64>) = match &node.op {
+            Source { .. } => (vec![], vec![]),
+            op => op
+                .history()
+                .iter()
+                .map(|&prior| {
+                    (
+                        self.nodes[self.index[&(prior as *const _)]].shadow,
+                        prior.value() as f64,
+                    )
+                })
+                .unzip(),
+        };
+        let shadow = match &node.op {
+            Source { .. } => value,
+            Sum { .. } => fold(&shadows, |a, b| a + b),
+            Difference { .. } => fold(&shadows, |a, b| a - b),
+            Product { .. } => fold(&shadows, |a, b| a * b),
+            Quotient { .. } => fold(&shadows, |a, b| a / b),
+            Compare { comparison, .. } => holds(*comparison, shadows[0], shadows[1]) as u8 as f64,
+            Select { .. } => match Branch::from_condition(shadows[0] as Num) {
+                Branch::Then => shadows[1],
+                Branch::Otherwise => shadows[2],
+            },
+            Choose { rule, .. } => pick(*rule, &shadows),
+            Other { op, history, .. } => {
+                for (prior, &shadow) in history.iter().zip(&shadows) {
+                    prior.op.value_cell().set(shadow as Num);
+                }
+                let shadow = op.reevaluate(history) as f64;
+                for (prior, &input) in history.iter().zip(&inputs) {
+                    prior.op.value_cell().set(input as Num);
+                }
+                shadow
+            }
+        };
+        let abs_error = (value - shadow).abs();
+        let finding = match &node.op {
+            Difference { .. } => {
+                let largest = inputs.iter().fold(0f64, |m, v| m.max(v.abs()));
+                let inexact = inputs
+                    .iter()
+                    .zip(&shadows)
+                    .any(|(input, shadow)| input != shadow);
+                let bits_lost = if value == 0. {
+                    24
+                } else {
+                    (largest / value.abs()).log2().floor().clamp(0., 24.) as u32
+                };
+                (inexact && largest > 0. && bits_lost >= CANCELLATION_BITS)
+                    .then_some(Finding::Cancellation { bits_lost })
+            }
+            Sum { .. } if inputs.len() >= LONG_SUM_TERMS => {
+                // just what this sum's own additions did, from its f32 inputs
+                let exact: f64 = inputs.iter().sum();
+                let rel_error = relative((value - exact).abs(), exact);
+                (rel_error > LONG_SUM_EPSILONS * Num::EPSILON as f64).then_some(Finding::LongSum {
+                    terms: inputs.len(),
+                    rel_error,
+                })
+            }
+            _ => None,
+        };
+        NodeError {
+            node,
+            shadow,
+            abs_error,
+            rel_error: relative(abs_error, shadow),
+            finding,
+        }
+    }
+
+    /// `op`'s entry, if it was part of the report
+    pub fn get(&self, op: &Operation<'a>) -> Option<&NodeError<'a>> {
+        self.index
+            .get(&(op as *const _))
+            .map(|&idx| &self.nodes[idx])
+    }
+
+    /// the nodes with something worth looking at
+    pub fn findings(&self) -> impl Iterator<Item = &NodeError<'a>> {
+        self.nodes.iter().filter(|node| node.finding.is_some())
+    }
+
+    /// a standalone HTML page with a row per node, findings highlighted
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+            "<title>Floating point error</title>\n<style>\n",
+            "body { font-family: sans-serif; }\n",
+            "table { border-collapse: collapse; }\n",
+            "th, td { padding: 2px 8px; text-align: right; border-bottom: 1px solid #ddd; }\n",
+            "td.node, th.node { text-align: left; }\n",
+            "tr.finding { background: #fdd; font-weight: bold; }\n",
+            "</style>\n</head>\n<body>\n<table>\n",
+            "<tr><th class=\"node\">node</th><th>kind</th><th>value</th><th>f64 value</th>",
+            "<th>abs error</th><th>rel error</th><th class=\"node\">finding</th></tr>\n",
+        ));
+        for entry in &self.nodes {
+            let class = if entry.finding.is_some() {
+                " class=\"finding\""
+            } else {
+                ""
+            };
+            html += &format!(
+                "<tr{class}><td class=\"node\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3e}</td><td>{:.3e}</td><td class=\"node\">{}</td></tr>\n",
+                escape_xml(&source_label(entry.node)),
+                entry.node.op.kind_name(),
+                entry.node.value(),
+                entry.shadow,
+                entry.abs_error,
+                entry.rel_error,
+                entry.finding.map(|f| f.to_string()).unwrap_or_default(),
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+impl<'a> Operation<'a> {
+    /// compares this and everything it was computed from against an `f64` evaluation of the
+    /// same graph, see the [module docs](self)
+    /// ```
+    ///# use explainability_rs::{Operation, OpArena, Finding};
+    /// let arena = OpArena::new();
+    /// let (op, op_r) = Operation::make_ctors(&arena);
+    /// let tenth = op(1.) / (op(10.), "a tenth");
+    /// let almost_one = tenth * (op(9.999), "almost one");
+    /// let tiny = almost_one - (op(0.9999), "tiny");
+    /// let report = tiny.precision_report();
+    /// assert!(matches!(report.get(tiny).unwrap().finding, Some(Finding::Cancellation { .. })));
+    /// ```
+    pub fn precision_report(&'a self) -> PrecisionReport<'a> {
+        PrecisionReport::new(&[self])
+    }
+}
@@ -0,0 +1,289 @@
+//! Rebuilds custom operators from their names, so graphs can go out as JSON and come back. Every
+//! [`Operator`] that wants this gives itself a stable [`Operator::name`], and puts whatever it
+//! needs to be rebuilt in its [`Operator::parameters`]. The registry maps each name back to a
+//! function that builds the operator from those parameters.
+
+use std::{borrow::Cow, cell::Cell, collections::BTreeMap, collections::HashMap, fmt};
+
+use serde_json::{Map, Value};
+
+use crate::{Branch, Error, Num, OpArena, Operation, OperationType, Operator, Row, Schedule};
+
+type Builder = Box<dyn Fn(&Map<String, Value>) -> Result<Box<dyn Operator>, String>>;
+
+/// Knows how to build operators by name. It starts out with the crate's own operators
+/// registered, and owns everything it builds, so it has to outlive any arena holding nodes made
+/// with them.
+/// ```
+///# use explainability_rs::{GraphBuilder, Operation, OpArena, Operator, OperatorRegistry, Schedule};
+/// let brackets = Schedule::marginal("tax", [(0., 0.1, "low"), (100., 0.2, "high")]);
+/// let arena = OpArena::new();
+/// let tax = brackets.operate(&[Operation::new_with_reason(150., "income", &arena)]);
+/// let json = GraphBuilder::new().export(&[("tax", tax)]).to_json();
+///
+/// let registry = OperatorRegistry::new();
+/// let rebuilt_arena = OpArena::new();
+/// let outputs = registry.rebuild(&json, &rebuilt_arena).unwrap();
+/// assert_eq!(outputs["tax"].value(), 20.);
+/// ```
+pub struct OperatorRegistry {
+    builders: HashMap<&'static str, Builder>,
+    built: typed_arena::Arena<Box<dyn Operator>>,
+}
+
+impl fmt::Debug for OperatorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.builders.keys().collect();
+        names.sort();
+        f.debug_struct("OperatorRegistry")
+            .field("names", &names)
+            .finish()
+    }
+}
+
+impl Default for OperatorRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OperatorRegistry {
+    /// a registry with [`Schedule`] and its [`Row`]s registered
+    pub fn new() -> Self {
+        let mut registry = OperatorRegistry {
+            builders: HashMap::new(),
+            built: typed_arena::Arena::new(),
+        };
+        registry
+            .register("schedule", |parameters| {
+                Ok(Box::new(Schedule::from_parameters(parameters)?))
+            })
+            .register("schedule row", |parameters| {
+                Ok(Box::new(Row::from_parameters(parameters)?))
+            });
+        registry
+    }
+
+    /// registers `build` as the way to make the operator called `name`, replacing whatever was
+    /// registered under it before. `build` gets the operator's parameters, or an empty map if it
+    /// had none, and says what's wrong with them if it can't use them
+    pub fn register<F>(&mut self, name: &'static str, build: F) -> &mut Self
+    where
+        F: Fn(&Map<String, Value>) -> Result<Box<dyn Operator>, String> + 'static,
+    {
+        self.builders.insert(name, Box::new(build));
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.builders.contains_key(name)
+    }
+
+    /// builds the operator registered as `name`. The registry keeps it alive for as long as the
+    /// registry is
+    pub fn build(
+        &self,
+        name: &str,
+        parameters: &Map<String, Value>,
+    ) -> Result<&dyn Operator, Error> {
+        let build = self
+            .builders
+            .get(name)
+            .ok_or_else(|| Error::UnknownOperator(name.into()))?;
+        let op = build(parameters).map_err(|problem| Error::InvalidParameters {
+            operator: name.into(),
+            problem,
+        })?;
+        Ok(&**self.built.alloc(op))
+    }
+
+    /// rebuilds a graph from the flat JSON made by [`crate::OperationGraph::to_json`], returning
+    /// its outputs by name. Every value is recomputed from the sources rather than trusted, so
+    /// the result is what the rebuilt operators actually give, and collapsed composites get
+    /// their inner graph built again. Custom operators get their inputs checked the way
+    /// [`Operator::try_operate`] does
+    pub fn rebuild<'a>(
+        &'a self,
+        json: &str,
+        arena: &'a OpArena<'a>,
+    ) -> Result<BTreeMap<String, &'a Operation<'a>>, Error> {
+        let document: Value = serde_json::from_str(json)?;
+        let invalid = |node, problem: &str| Error::InvalidGraph {
+            node,
+            problem: problem.into(),
+        };
+        let entries = document["nodes"]
+            .as_array()
+            .ok_or_else(|| invalid(0, "the graph has no list of nodes"))?;
+        let mut inputs = Vec::with_capacity(entries.len());
+        for (id, entry) in entries.iter().enumerate() {
+            let ids: Option<Vec<usize>> = entry["inputs"].as_array().and_then(|ids| {
+                ids.iter()
+                    .map(|input| input.as_u64().map(|input| input as usize))
+                    .collect()
+            });
+            match ids {
+                Some(ids) if ids.iter().all(|&input| input < entries.len()) => inputs.push(ids),
+                _ => return Err(invalid(id, "has inputs that aren't node ids")),
+            }
+        }
+
+        // inputs get built before the nodes using them, and a node found on the stack again
+        // before it's built means the graph loops back on itself
+        let mut nodes: Vec<Option<&'a Operation<'a>>> = vec![None; entries.len()];
+        let mut expanded = vec![false; entries.len()];
+        for root in 0..entries.len() {
+            let mut stack = vec![root];
+            while let Some(&id) = stack.last() {
+                if nodes[id].is_some() {
+                    stack.pop();
+                    continue;
+                }
+                let pending: Vec<usize> = inputs[id]
+                    .iter()
+                    .copied()
+                    .filter(|&input| nodes[input].is_none())
+                    .collect();
+                if pending.is_empty() {
+                    let history = inputs[id]
+                        .iter()
+                        .filter_map(|&input| nodes[input])
+                        .collect();
+                    nodes[id] = Some(self.rebuild_node(id, &entries[id], history, arena)?);
+                    stack.pop();
+                } else if pending.iter().any(|&input| expanded[input]) {
+                    return Err(invalid(id, "depends on itself"));
+                } else {
+                    expanded[id] = true;
+                    stack.extend(pending);
+                }
+            }
+        }
+
+        let outputs = document["outputs"]
+            .as_object()
+            .ok_or_else(|| invalid(0, "the graph has no map of outputs"))?;
+        outputs
+            .iter()
+            .map(|(name, id)| {
+                let node = id
+                    .as_u64()
+                    .and_then(|id| nodes.get(id as usize).copied().flatten())
+                    .ok_or_else(|| invalid(0, &format!("output `{name}` isn't a node id")))?;
+                Ok((name.clone(), node))
+            })
+            .collect()
+    }
+
+    fn rebuild_node<'a>(
+        &'a self,
+        id: usize,
+        entry: &Value,
+        history: Vec<&'a Operation<'a>>,
+        arena: &'a OpArena<'a>,
+    ) -> Result<&'a Operation<'a>, Error> {
+        let invalid = |problem: &str| Error::InvalidGraph {
+            node: id,
+            problem: problem.into(),
+        };
+        let needs = |fits: bool, problem| if fits { Ok(()) } else { Err(invalid(problem)) };
+        let reason = match &entry["reason"] {
+            Value::String(reason) => Some(Cow::Owned(reason.clone())),
+            Value::Null => None,
+            _ => return Err(invalid("has a reason that isn't a string")),
+        };
+        let kind = entry["kind"].as_str().unwrap_or_default();
+        let op = match kind {
+            "Source" => {
+                // serde_json writes NaN and infinities as null
+                let value = match &entry["value"] {
+                    Value::Null => Num::NAN,
+                    value => value
+                        .as_f64()
+                        .ok_or_else(|| invalid("has a value that isn't a number"))?
+                        as Num,
+                };
+                let name = entry["name"].as_str().map(|name| Cow::Owned(name.into()));
+                OperationType::make_source(value, name)
+            }
+            "Sum" | "Difference" | "Product" | "Quotient" => {
+                needs(!history.is_empty(), "has no inputs")?;
+                match kind {
+                    "Sum" => OperationType::make_sum(0., history),
+                    "Difference" => OperationType::make_difference(0., history),
+                    "Product" => OperationType::make_product(0., history),
+                    _ => OperationType::make_quotient(0., history),
+                }
+            }
+            "Compare" => {
+                needs(history.len() == 2, "should have 2 inputs")?;
+                OperationType::Compare {
+                    value: Cell::new(0.),
+                    comparison: serde_json::from_value(entry["comparison"].clone())
+                        .map_err(|_| invalid("has no valid comparison"))?,
+                    history,
+                }
+            }
+            "Select" => {
+                needs(history.len() == 3, "should have 3 inputs")?;
+                OperationType::Select {
+                    value: Cell::new(0.),
+                    taken: Cell::new(Branch::Then),
+                    history,
+                }
+            }
+            "Choose" => {
+                let rule: crate::Choice = serde_json::from_value(entry["rule"].clone())
+                    .map_err(|_| invalid("has no valid rule"))?;
+                if rule == crate::Choice::Clamp {
+                    needs(history.len() == 3, "should have 3 inputs")?;
+                } else {
+                    needs(!history.is_empty(), "has no inputs")?;
+                }
+                OperationType::Choose {
+                    value: Cell::new(0.),
+                    rule,
+                    picked: Cell::new(0),
+                    margin: Cell::new(0.),
+                    history,
+                }
+            }
+            "Other" => {
+                let operator = &entry["operator"];
+                let name = operator["name"].as_str().ok_or_else(|| {
+                    invalid("uses an operator without a registered name, so it can't be rebuilt")
+                })?;
+                let empty = Map::new();
+                let parameters = operator["parameters"].as_object().unwrap_or(&empty);
+                let op = self.build(name, parameters)?;
+                // a collapsed composite was exported without its inner graph, so it gets built
+                // again from the inputs
+                let collapsed = entry["collapsed"] == true && op.is_composite();
+                // the operator's own inputs, without what else it keeps in its history, like a
+                // composite's inner graph or a schedule's rows
+                let inputs = if op.is_composite() && !collapsed {
+                    &history[..history.len().saturating_sub(1)]
+                } else {
+                    &history[..]
+                };
+                let inputs = match op.arity() {
+                    Some(arity) => inputs.get(..arity).unwrap_or(inputs),
+                    None => inputs,
+                };
+                crate::error::check_inputs(op, inputs)?;
+                if collapsed {
+                    needs(!history.is_empty(), "has no inputs")?;
+                    return Ok(op.operate(&history));
+                }
+                OperationType::Other {
+                    value: Cell::new(0.),
+                    op,
+                    history,
+                }
+            }
+            _ => return Err(invalid("has an unknown kind")),
+        };
+        op.reevaluate();
+        Ok(Operation::alloc(arena, op, reason))
+    }
+}
@@ -0,0 +1,331 @@
+//! Piecewise operators built from a table, for things like tax brackets, tiered pricing and rate
+//! tables. The point is for the graph to read like the published schedule: each row that applied
+//! shows up as its own node with its own reason, feeding the total.
+
+use std::borrow::Cow;
+
+use serde_json::{Map, Value};
+
+use crate::{Num, Operation, Operator};
+
+/// How a [`Schedule`] turns its rows into a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleKind {
+    /// every row applies its rate to the part of the input that falls in its range, and the
+    /// result is the sum of those parts. This is how income tax brackets work
+    Marginal,
+    /// only the row whose range contains the input applies, and its value is the result. This
+    /// is a plain lookup table
+    Lookup,
+}
+
+impl ScheduleKind {
+    fn name(self) -> &'static str {
+        match self {
+            ScheduleKind::Marginal => "marginal",
+            ScheduleKind::Lookup => "lookup",
+        }
+    }
+
+    fn from_parameters(parameters: &Map<String, Value>) -> Result<Self, String> {
+        match parameters.get("kind").and_then(Value::as_str) {
+            Some("marginal") => Ok(ScheduleKind::Marginal),
+            Some("lookup") => Ok(ScheduleKind::Lookup),
+            _ => Err("`kind` should be \"marginal\" or \"lookup\"".into()),
+        }
+    }
+}
+
+/// reads a number parameter, where `null` is NaN since that's how serde_json writes it
+fn number(parameters: &Map<String, Value>, key: &str) -> Result<Num, String> {
+    match parameters.get(key) {
+        Some(Value::Null) => Ok(Num::NAN),
+        Some(value) => value
+            .as_f64()
+            .map(|n| n as Num)
+            .ok_or_else(|| format!("`{key}` should be a number")),
+        None => Err(format!("`{key}` is missing")),
+    }
+}
+
+/// One row of a [`Schedule`], covering the inputs from `lower` up to the next row's `lower`
+#[derive(Debug, Clone)]
+pub struct Row {
+    lower: Num,
+    upper: Option<Num>,
+    rate: Num,
+    reason: Cow<'static, str>,
+    kind: ScheduleKind,
+}
+
+impl Row {
+    /// the row's breakpoint, where its range starts
+    pub fn lower(&self) -> Num {
+        self.lower
+    }
+
+    /// the next row's breakpoint, or `None` for the last row
+    pub fn upper(&self) -> Option<Num> {
+        self.upper
+    }
+
+    /// the rate applied to the row's slice of the input, or the value a lookup gives back
+    pub fn rate(&self) -> Num {
+        self.rate
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// how much of `input` this row is responsible for
+    fn contribution(&self, input: Num) -> Num {
+        match self.kind {
+            ScheduleKind::Marginal => {
+                let top = self.upper.map_or(input, |upper| input.min(upper));
+                (top - self.lower).max(0.) * self.rate
+            }
+            ScheduleKind::Lookup => self.rate,
+        }
+    }
+
+    fn applies_to(&self, input: Num) -> bool {
+        match self.kind {
+            ScheduleKind::Marginal => input > self.lower,
+            ScheduleKind::Lookup => {
+                input >= self.lower && self.upper.is_none_or(|upper| input < upper)
+            }
+        }
+    }
+
+    /// the built-in builder for rows in an [`crate::OperatorRegistry`]
+    pub(crate) fn from_parameters(parameters: &Map<String, Value>) -> Result<Self, String> {
+        let upper = match parameters.get("upper") {
+            None | Some(Value::Null) => None,
+            Some(_) => Some(number(parameters, "upper")?),
+        };
+        let reason = match parameters.get("reason") {
+            Some(Value::String(reason)) => reason.clone(),
+            _ => return Err("`reason` should be a string".into()),
+        };
+        Ok(Row {
+            lower: number(parameters, "lower")?,
+            upper,
+            rate: number(parameters, "rate")?,
+            reason: reason.into(),
+            kind: ScheduleKind::from_parameters(parameters)?,
+        })
+    }
+}
+
+impl Operator for Row {
+    fn symbol(&self) -> &'static str {
+        " (row) "
+    }
+    fn name(&self) -> Option<&'static str> {
+        Some("schedule row")
+    }
+    fn arity(&self) -> Option<usize> {
+        Some(1)
+    }
+    fn parameters(&self) -> Vec<(&'static str, serde_json::Value)> {
+        vec![
+            ("lower", self.lower.into()),
+            ("upper", self.upper.into()),
+            ("rate", self.rate.into()),
+            ("reason", self.reason.as_ref().into()),
+            ("kind", self.kind.name().into()),
+        ]
+    }
+    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+        let input = ops[0];
+        Operation::apply_with_reason(
+            self,
+            &[input],
+            self.evaluate(&[input.value()]),
+            &self.reason,
+        )
+    }
+    /// a lookup row stops contributing once a rebind moves the input out of its range
+    fn evaluate(&self, values: &[Num]) -> Num {
+        let input = values[0];
+        if self.applies_to(input) {
+            self.contribution(input)
+        } else {
+            0.
+        }
+    }
+}
+
+/// A piecewise function defined by a table of breakpoints, with a reason for every row.
+/// ```
+///# use explainability_rs::{Operation, OpArena, Operator, Schedule};
+/// let brackets = Schedule::marginal(
+///     "income tax",
+///     [
+///         (0., 0.10, "10% bracket"),
+///         (11_000., 0.12, "12% bracket"),
+///         (44_725., 0.22, "22% bracket"),
+///     ],
+/// );
+/// let arena = OpArena::new();
+/// let income = Operation::new_with_reason(50_000., "income", &arena);
+/// let tax = brackets.operate(&[income]);
+/// // 1100 + 4047 + 1160.5
+/// assert!((tax.value() - 6_307.5).abs() < 0.1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    name: Cow<'static, str>,
+    kind: ScheduleKind,
+    rows: Vec<Row>,
+}
+
+impl Schedule {
+    /// makes a schedule where each row's rate applies to the slice of the input between its
+    /// breakpoint and the next one. Rows are `(breakpoint, rate, reason)`.
+    ///
+    /// # Panics
+    /// if the breakpoints aren't strictly increasing
+    pub fn marginal<R>(
+        name: impl Into<Cow<'static, str>>,
+        rows: impl IntoIterator<Item = (Num, Num, R)>,
+    ) -> Self
+    where
+        R: Into<Cow<'static, str>>,
+    {
+        Self::from_rows(name.into(), rows, ScheduleKind::Marginal)
+    }
+
+    /// makes a schedule that looks up the row whose range contains the input and returns its
+    /// value. Rows are `(breakpoint, value, reason)`, and inputs below the first breakpoint
+    /// don't match any row, giving 0.
+    ///
+    /// # Panics
+    /// if the breakpoints aren't strictly increasing
+    pub fn lookup<R>(
+        name: impl Into<Cow<'static, str>>,
+        rows: impl IntoIterator<Item = (Num, Num, R)>,
+    ) -> Self
+    where
+        R: Into<Cow<'static, str>>,
+    {
+        Self::from_rows(name.into(), rows, ScheduleKind::Lookup)
+    }
+
+    fn from_rows<R>(
+        name: Cow<'static, str>,
+        rows: impl IntoIterator<Item = (Num, Num, R)>,
+        kind: ScheduleKind,
+    ) -> Self
+    where
+        R: Into<Cow<'static, str>>,
+    {
+        let mut rows: Vec<Row> = rows
+            .into_iter()
+            .map(|(lower, rate, reason)| Row {
+                lower,
+                upper: None,
+                rate,
+                reason: reason.into(),
+                kind,
+            })
+            .collect();
+        for idx in 1..rows.len() {
+            let lower = rows[idx].lower;
+            assert!(
+                rows[idx - 1].lower < lower,
+                "schedule breakpoints must be strictly increasing"
+            );
+            rows[idx - 1].upper = Some(lower);
+        }
+        Schedule { name, kind, rows }
+    }
+
+    pub fn kind(&self) -> ScheduleKind {
+        self.kind
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// the built-in builder for schedules in an [`crate::OperatorRegistry`]. Unlike the
+    /// constructors, this reports breakpoints that aren't strictly increasing instead of
+    /// panicking, since they come from outside the program
+    pub(crate) fn from_parameters(parameters: &Map<String, Value>) -> Result<Self, String> {
+        let name = match parameters.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            _ => return Err("`name` should be a string".into()),
+        };
+        let kind = ScheduleKind::from_parameters(parameters)?;
+        let Some(Value::Array(rows)) = parameters.get("rows") else {
+            return Err("`rows` should be a list".into());
+        };
+        let mut parsed = Vec::with_capacity(rows.len());
+        for row in rows {
+            let (lower, rate, reason) = match row.as_array().map(Vec::as_slice) {
+                Some([lower, rate, Value::String(reason)]) => (lower, rate, reason),
+                _ => return Err("each row should be `[breakpoint, rate, reason]`".into()),
+            };
+            let (Some(lower), Some(rate)) = (lower.as_f64(), rate.as_f64()) else {
+                return Err("row breakpoints and rates should be numbers".into());
+            };
+            parsed.push((lower as Num, rate as Num, reason.clone()));
+        }
+        if parsed.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err("schedule breakpoints must be strictly increasing".into());
+        }
+        Ok(Self::from_rows(name.into(), parsed, kind))
+    }
+}
+
+impl Operator for Schedule {
+    fn symbol(&self) -> &'static str {
+        " (schedule) "
+    }
+    fn name(&self) -> Option<&'static str> {
+        Some("schedule")
+    }
+    fn arity(&self) -> Option<usize> {
+        Some(1)
+    }
+    fn description(&self) -> Option<Cow<'_, str>> {
+        Some(
+            format!(
+                "{} schedule {} with {} rows",
+                self.kind.name(),
+                self.name,
+                self.rows.len()
+            )
+            .into(),
+        )
+    }
+    fn parameters(&self) -> Vec<(&'static str, serde_json::Value)> {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| serde_json::json!([row.lower, row.rate, row.reason]))
+            .collect();
+        vec![
+            ("name", self.name.as_ref().into()),
+            ("kind", self.kind.name().into()),
+            ("rows", serde_json::Value::Array(rows)),
+        ]
+    }
+    /// applies the schedule to `ops[0]`. The result's history is the input followed by one node
+    /// per row, each holding that row's share of the result, which is 0 for rows that don't
+    /// apply. Every row gets a node so that rebinding the input into another row keeps the
+    /// result the sum of its rows
+    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+        let input = ops[0];
+        let mut history = vec![input];
+        history.extend(self.rows.iter().map(|row| row.operate(&[input])));
+        let value = history[1..].iter().map(|row| row.value()).sum();
+        Operation::apply_with_reason(self, &history, value, &self.name)
+    }
+    /// the sum of the rows, which come after the input
+    fn evaluate(&self, values: &[Num]) -> Num {
+        values[1..].iter().sum()
+    }
+}
@@ -0,0 +1,278 @@
+//! Monte Carlo simulation over the recorded graph. Sources get distributions, and the graph is
+//! re-evaluated once per draw, the same way [`crate::Context::bind`] re-evaluates it, so the
+//! code that built it never has to run again.
+//! ```
+//!# use explainability_rs::{Distribution, Operation, OpArena, Simulation};
+//! let arena = OpArena::new();
+//! let (_, op_r) = Operation::make_ctors(&arena);
+//! let units = op_r(1000., "units sold");
+//! let price = op_r(20., "price");
+//! let costs = op_r(15000., "fixed costs");
+//! let profit = units * price - (costs, "profit");
+//! let result = Simulation::new(profit)
+//!     .vary(units, Distribution::Triangular { lo: 600., mode: 1000., hi: 1200. })
+//!     .vary(costs, Distribution::Normal { mean: 15000., std_dev: 1000. })
+//!     .seed(7)
+//!     .run(2000);
+//! assert!(result.percentile(5.) < result.percentile(95.));
+//! // units move profit around far more than costs do
+//! assert!(std::ptr::eq(result.correlations()[0].0, units));
+//! println!("{result}\n{}", result.histogram(10));
+//! ```
+
+use std::fmt;
+
+use crate::{attribution::source_label, attribution::Evaluator, rng::SplitMix64, Num, Operation};
+
+/// What values a source can take in a [`Simulation`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    /// anywhere in `[lo, hi)`, equally likely
+    Uniform {
+        lo: Num,
+        hi: Num,
+    },
+    Normal {
+        mean: Num,
+        std_dev: Num,
+    },
+    /// between `lo` and `hi`, most likely at `mode` and tapering off linearly either side
+    Triangular {
+        lo: Num,
+        mode: Num,
+        hi: Num,
+    },
+    /// one of these values, each as likely as any other. Repeat a value to make it more likely
+    Empirical(Vec<Num>),
+}
+
+impl Distribution {
+    fn sample(&self, rng: &mut SplitMix64) -> Num {
+        match self {
+            Distribution::Uniform { lo, hi } => lo + (hi - lo) * rng.next_f64() as Num,
+            Distribution::Normal { mean, std_dev } => mean + std_dev * rng.next_normal() as Num,
+            &Distribution::Triangular { lo, mode, hi } => {
+                let u = rng.next_f64() as Num;
+                let split = if hi > lo { (mode - lo) / (hi - lo) } else { 0. };
+                if u < split {
+                    lo + (u * (hi - lo) * (mode - lo)).sqrt()
+                } else {
+                    hi - ((1. - u) * (hi - lo) * (hi - mode)).sqrt()
+                }
+            }
+            Distribution::Empirical(values) => values[rng.below(values.len())],
+        }
+    }
+}
+
+/// Sets up a Monte Carlo run over the graph under a root
+pub struct Simulation<'a> {
+    root: &'a Operation<'a>,
+    varied: Vec<(&'a Operation<'a>, Distribution)>,
+    seed: u64,
+}
+
+impl<'a> Simulation<'a> {
+    pub fn new(root: &'a Operation<'a>) -> Self {
+        Simulation {
+            root,
+            varied: vec![],
+            seed: 0,
+        }
+    }
+
+    /// draws `source` from `distribution` on every run. Sources that aren't varied keep their
+    /// current values
+    ///
+    /// # Panics
+    /// if `source` isn't a source, or `distribution` is an empty [`Distribution::Empirical`]
+    pub fn vary(mut self, source: &'a Operation<'a>, distribution: Distribution) -> Self {
+        assert!(
+            matches!(source.op, crate::OperationType::Source { .. }),
+            "only sources can be varied"
+        );
+        assert!(
+            !matches!(&distribution, Distribution::Empirical(values) if values.is_empty()),
+            "an empirical distribution needs at least one value"
+        );
+        self.varied.push((source, distribution));
+        self
+    }
+
+    /// the seed for the draws, 0 by default. The same seed always gives the same results
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// re-evaluates the graph `runs` times, then puts it back the way it was
+    pub fn run(&self, runs: usize) -> SimulationResult<'a> {
+        let inputs: Vec<_> = self.varied.iter().map(|&(source, _)| source).collect();
+        let evaluator = Evaluator::new(self.root, &inputs);
+        let mut rng = SplitMix64::new(self.seed);
+        let mut draws = vec![Vec::with_capacity(runs); inputs.len()];
+        let mut outputs = Vec::with_capacity(runs);
+        let mut values = vec![0.; inputs.len()];
+        for _ in 0..runs {
+            for ((value, (_, distribution)), column) in
+                values.iter_mut().zip(&self.varied).zip(&mut draws)
+            {
+                *value = distribution.sample(&mut rng);
+                column.push(*value);
+            }
+            outputs.push(evaluator.evaluate(&values));
+        }
+        drop(evaluator);
+        SimulationResult {
+            root: self.root,
+            outputs,
+            inputs: inputs.into_iter().zip(draws).collect(),
+        }
+    }
+}
+
+/// Every draw of a [`Simulation`], and what the output came out as each time
+pub struct SimulationResult<'a> {
+    pub root: &'a Operation<'a>,
+    /// the output on each run
+    pub outputs: Vec<Num>,
+    /// each varied source with the value it was given on each run
+    pub inputs: Vec<(&'a Operation<'a>, Vec<Num>)>,
+}
+
+/// Counts of outputs in equal-width bins between `lo` and `hi`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub lo: Num,
+    pub hi: Num,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    pub fn bin_width(&self) -> Num {
+        (self.hi - self.lo) / self.counts.len().max(1) as Num
+    }
+}
+
+impl fmt::Display for Histogram {
+    /// one bar per bin, scaled to the fullest one
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let most = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        for (idx, &count) in self.counts.iter().enumerate() {
+            let start = self.lo + idx as Num * self.bin_width();
+            let bar = "#".repeat(count * 40 / most);
+            writeln!(f, "{start:>12} {count:>7} {bar}")?;
+        }
+        Ok(())
+    }
+}
+
+fn mean(values: &[Num]) -> f64 {
+    values.iter().map(|&v| v as f64).sum::<f64>() / values.len().max(1) as f64
+}
+
+fn correlation(xs: &[Num], ys: &[Num]) -> Num {
+    let (mx, my) = (mean(xs), mean(ys));
+    let (mut cov, mut vx, mut vy) = (0., 0., 0.);
+    for (&x, &y) in xs.iter().zip(ys) {
+        let (dx, dy) = (x as f64 - mx, y as f64 - my);
+        cov += dx * dy;
+        vx += dx * dx;
+        vy += dy * dy;
+    }
+    if vx == 0. || vy == 0. {
+        return 0.;
+    }
+    (cov / (vx * vy).sqrt()) as Num
+}
+
+impl<'a> SimulationResult<'a> {
+    pub fn mean(&self) -> Num {
+        mean(&self.outputs) as Num
+    }
+
+    pub fn std_dev(&self) -> Num {
+        let mean = mean(&self.outputs);
+        let variance = self
+            .outputs
+            .iter()
+            .map(|&v| (v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.outputs.len().saturating_sub(1).max(1) as f64;
+        variance.sqrt() as Num
+    }
+
+    /// the output below which `p` percent of the runs fell, interpolating between runs. NaN if
+    /// there weren't any runs
+    pub fn percentile(&self, p: Num) -> Num {
+        let mut sorted = self.outputs.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let Some(last) = sorted.len().checked_sub(1) else {
+            return Num::NAN;
+        };
+        let position = (p.clamp(0., 100.) / 100.) * last as Num;
+        let below = position.floor() as usize;
+        let above = position.ceil() as usize;
+        sorted[below] + (sorted[above] - sorted[below]) * (position - below as Num)
+    }
+
+    /// the outputs counted into `bins` equal-width bins spanning all of them
+    pub fn histogram(&self, bins: usize) -> Histogram {
+        let bins = bins.max(1);
+        let finite = self.outputs.iter().copied().filter(|v| v.is_finite());
+        let (lo, hi) = finite.fold((Num::INFINITY, Num::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(v), hi.max(v))
+        });
+        let mut counts = vec![0; bins];
+        if lo > hi {
+            return Histogram {
+                lo: 0.,
+                hi: 0.,
+                counts,
+            };
+        }
+        let width = (hi - lo) / bins as Num;
+        for &output in self.outputs.iter().filter(|v| v.is_finite()) {
+            let bin = if width > 0. {
+                (((output - lo) / width) as usize).min(bins - 1)
+            } else {
+                0
+            };
+            counts[bin] += 1;
+        }
+        Histogram { lo, hi, counts }
+    }
+
+    /// each varied source's correlation with the output, strongest first, whichever direction
+    /// it goes in
+    pub fn correlations(&self) -> Vec<(&'a Operation<'a>, Num)> {
+        let mut correlations: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|(source, draws)| (*source, correlation(draws, &self.outputs)))
+            .collect();
+        correlations.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+        correlations
+    }
+}
+
+impl fmt::Display for SimulationResult<'_> {
+    /// summary statistics, then the inputs ranked by correlation with the output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} runs, mean {}, std dev {}",
+            self.outputs.len(),
+            self.mean(),
+            self.std_dev()
+        )?;
+        for p in [5., 25., 50., 75., 95.] {
+            writeln!(f, "p{p:<3} {}", self.percentile(p))?;
+        }
+        write!(f, "correlation with output:")?;
+        for (source, correlation) in self.correlations() {
+            write!(f, "\n  {:>6.3} {}", correlation, source_label(source))?;
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,11 @@
+use crate::Interval;
 use crate::Operation;
 use crate::OperationType;
 use crate::Operator;
 use typed_arena::Arena;
 
 #[test]
+#[allow(clippy::redundant_pattern_matching)]
 fn test_sum_reasons() {
     fn within_point1(val: f32, target: f32) -> bool {
         target - 0.1 < val && val < target + 0.1
@@ -19,7 +21,7 @@ fn test_sum_reasons() {
     assert!(matches!(&a_plus_b.reason, Some(r) if r == "b"));
     let continuing_sum = a_plus_b + a;
     assert!(
-        matches!(&continuing_sum.op, Sum { value, history } if history.len() == 3 && *value < 4.1)
+        matches!(&continuing_sum.op, Sum { value, history } if history.len() == 3 && value.get() < 4.1)
     );
     assert!(matches!(&continuing_sum.reason, Some(r) if r == "b"));
     let c = Operation::new_with_reason(3.0, "c", &arena);
@@ -35,7 +37,7 @@ fn test_sum_reasons() {
                       Operation { op: Source{ .. }, reason: None, .. },
                       Operation { op: Source{ .. }, reason: Some(r2), .. },
                       Operation { op: Source{ .. }, reason: Some(r3), .. }
-        ] if r1 == "a" && r2 == "a" && r3 == "c") && within_point1(*value, 7.)
+        ] if r1 == "a" && r2 == "a" && r3 == "c") && within_point1(value.get(), 7.)
     ));
     dbg!(web_graph(continuing_sum));
     println!("{}", continuing_sum.as_json());
@@ -62,16 +64,28 @@ impl Operator for Sqrt {
     fn symbol(&self) -> &'static str {
         " sqrt "
     }
-    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation {
-        let operand = ops[0];
-        operand._allocator.alloc(Operation {
-            op: OperationType::Other {
-                value: f32::sqrt(operand.op.value()),
-                op: self,
-                history: vec![operand],
-            },
-            reason: None,
-            _allocator: operand._allocator,
+    fn evaluate(&self, values: &[f32]) -> f32 {
+        values[0].sqrt()
+    }
+    fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+        Operation::evaluated(self, ops)
+    }
+    fn arity(&self) -> Option<usize> {
+        Some(1)
+    }
+    fn check_input(&self, _: usize, value: f32) -> Result<(), &'static str> {
+        if value < 0. {
+            Err("is negative")
+        } else {
+            Ok(())
+        }
+    }
+    fn interval(&self, history: &[Interval]) -> Option<Interval> {
+        // sqrt is correctly rounded, so one step out either side covers the exact result
+        let x = history[0];
+        (x.hi >= 0.).then(|| Interval {
+            lo: x.lo.max(0.).sqrt().next_down().max(0.),
+            hi: x.hi.sqrt().next_up(),
         })
     }
 }
@@ -143,6 +157,28 @@ fn chained_add() {
     dbg!(web_graph(chain_sum));
 }
 
+#[test]
+fn iter_sum_is_flat() {
+    let alloc = Arena::new();
+    let chain_sum: &Operation = (1..=10).map(|n| Operation::new(n as f32, &alloc)).sum();
+    assert_eq!(chain_sum.value(), 55.);
+    assert!(matches!(&chain_sum.op, OperationType::Sum { history, .. } if history.len() == 10));
+    // one node for the sum, 10 for the sources, nothing in between
+    assert_eq!(alloc.len(), 11);
+
+    let (op, op_r) = Operation::make_ctors(&alloc);
+    let explained = op_r(4., "four") + (op(5.), "nine");
+    let total = Operation::sum_with_reason([chain_sum, explained, op(1.)], "total");
+    assert_eq!(total.value(), 65.);
+    assert!(matches!(&total.reason, Some(r) if r == "total"));
+    // the reasonless sum gets flattened, the explained one stays intact
+    assert!(matches!(&total.op, OperationType::Sum { history, .. } if history.len() == 12));
+
+    let product: &Operation = [op(2.), op(3.), op(4.)].into_iter().product();
+    assert_eq!(product.value(), 24.);
+    assert!(matches!(&product.op, OperationType::Product { history, .. } if history.len() == 3));
+}
+
 #[test]
 fn non_commutative() {
     let alloc = Arena::new();
@@ -151,4 +187,1357 @@ fn non_commutative() {
     assert_eq!(a.value(), 6. / 3.);
     let c = a / op(3.);
     assert_eq!(c.value(), 6. / 3. / 3.);
+    // folding only happens on the left, so the history still means the same thing
+    let d = op(1.) / c;
+    assert_eq!(d.value(), 1. / (6. / 3. / 3.));
+    assert!(matches!(&d.op, OperationType::Quotient { history, .. } if history.len() == 2));
+    let e = op(10.) - (op(4.) - op(1.));
+    assert_eq!(e.value(), 7.);
+    assert!(matches!(&e.op, OperationType::Difference { history, .. } if history.len() == 2));
+}
+
+#[test]
+fn select_records_branch() {
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+    let income = op_r(50_000., "income");
+    let threshold = op_r(40_000., "threshold");
+    let high = op_r(0.3, "high rate");
+    let low = op_r(0.2, "low rate");
+    let over = income.greater_than(threshold);
+    assert_eq!(over.value(), 1.);
+    assert_eq!(threshold.greater_or_equal(income).value(), 0.);
+    let rate = Operation::select(over, high, low);
+    assert_eq!(rate.value(), high.value());
+    assert!(matches!(
+        &rate.op,
+        OperationType::Select { taken, history, .. }
+            if taken.get() == crate::Branch::Then && history.len() == 3
+    ));
+    let tax = income * (rate, "tax");
+    let graph = tax.as_graphviz(crate::visualization::GraphDirection::DataFlow);
+    // only the low rate node and its edge into the select get greyed out
+    assert_eq!(graph.matches("[color=\"gray\"]").count(), 2);
+    assert!(graph.contains("true (>)"));
+    // both JSON exports say which branch was taken
+    assert!(rate.as_json().contains("\"taken\": \"Then\""));
+    let flat = crate::GraphBuilder::new()
+        .export(&[("rate", rate)])
+        .to_json();
+    let flat: serde_json::Value = serde_json::from_str(&flat).unwrap();
+    assert_eq!(flat["nodes"][0]["taken"], "Then");
+}
+
+#[test]
+fn min_max_clamp_pick_a_winner() {
+    let alloc = Arena::new();
+    let (op, op_r) = Operation::make_ctors(&alloc);
+    let standard = op_r(13_850., "standard deduction");
+    let itemized = op_r(12_000., "itemized deductions");
+    let deduction = standard.max(itemized);
+    assert_eq!(deduction.value(), 13_850.);
+    assert!(matches!(
+        &deduction.op,
+        OperationType::Choose { picked, margin, .. } if picked.get() == 0 && margin.get() == 1_850.
+    ));
+    assert!(deduction.as_json().contains("\"picked\": 0"));
+    let flat = crate::GraphBuilder::new()
+        .export(&[("deduction", deduction)])
+        .to_json();
+    let flat: serde_json::Value = serde_json::from_str(&flat).unwrap();
+    assert_eq!(flat["nodes"][0]["rule"], "Max");
+    assert_eq!(flat["nodes"][0]["picked"], 0);
+    assert_eq!(flat["nodes"][0]["margin"], 1_850.);
+    let graph = deduction.as_graphviz(crate::visualization::GraphDirection::DataFlow);
+    assert!(graph.contains("picked by 1850"));
+
+    let smallest = Operation::min_of([op(3.), op(1.), op(2.), op(1.)]);
+    assert!(matches!(
+        &smallest.op,
+        OperationType::Choose { picked, margin, value, .. }
+            if picked.get() == 1 && margin.get() == 0. && value.get() == 1.
+    ));
+
+    let clamped = op(12.).clamp(op(0.), op(10.));
+    assert_eq!(clamped.value(), 10.);
+    assert!(matches!(
+        &clamped.op,
+        OperationType::Choose { picked, margin, .. } if picked.get() == 2 && margin.get() == 2.
+    ));
+    let in_range = op(5.).clamp(op(0.), op(10.));
+    assert!(matches!(
+        &in_range.op,
+        OperationType::Choose { picked, margin, .. } if picked.get() == 0 && margin.get() == 0.
+    ));
+}
+
+#[test]
+fn schedule_rows() {
+    use crate::Schedule;
+    let brackets = Schedule::marginal(
+        "income tax",
+        [
+            (0., 0.10, "10% bracket"),
+            (11_000., 0.12, "12% bracket"),
+            (44_725., 0.22, "22% bracket"),
+            (95_375., 0.24, "24% bracket"),
+        ],
+    );
+    let alloc = Arena::new();
+    let income = Operation::new_with_reason(50_000., "income", &alloc);
+    let tax = brackets.operate(&[income]);
+    assert!(matches!(&tax.reason, Some(r) if r == "income tax"));
+    // the input, then one node per bracket, with nothing from the ones that didn't apply
+    let OperationType::Other { history, value, .. } = &tax.op else {
+        panic!("schedules should make an Other node");
+    };
+    assert_eq!(history.len(), 5);
+    assert!(matches!(&history[3].reason, Some(r) if r == "22% bracket"));
+    let parts: Vec<_> = history[1..].iter().map(|row| row.value()).collect();
+    assert_eq!(parts, [1_100., 4_047., 0.22 * 5_275., 0.]);
+    assert_eq!(value.get(), parts.iter().sum::<f32>());
+    let row = &brackets.rows()[1];
+    assert_eq!(
+        (row.lower(), row.upper(), row.rate()),
+        (11_000., Some(44_725.), 0.12)
+    );
+    assert_eq!(row.reason(), "12% bracket");
+    assert_eq!(brackets.rows()[3].upper(), None);
+
+    let shipping = Schedule::lookup(
+        "shipping",
+        [(0., 5., "small"), (1., 8., "medium"), (5., 20., "large")],
+    );
+    let cost = shipping.operate(&[Operation::new(3., &alloc)]);
+    assert_eq!(cost.value(), 8.);
+    assert!(matches!(
+        &cost.op,
+        OperationType::Other { history, .. } if history.len() == 4
+            && matches!(&history[2].reason, Some(r) if r == "medium")
+            && history[2].value() == 8.
+            && history[1].value() == 0.
+    ));
+}
+
+#[test]
+fn context_rebinds_inputs() {
+    use crate::Context;
+    let alloc = Arena::new();
+    let mut ctx = Context::new(&alloc);
+    let income = ctx.input("income", 50_000., "gross income");
+    let threshold = ctx.input("threshold", 40_000., "high rate threshold");
+    let deduction = ctx.input("deduction", 5_000., "flat deduction");
+    let (_, op_r) = Operation::make_ctors(&alloc);
+    let rate = Operation::select(
+        income.greater_than(threshold),
+        op_r(0.3, "high rate"),
+        op_r(0.2, "low rate"),
+    );
+    let taxable = (income - deduction).max(op_r(0., "floor"));
+    let tax = taxable * (rate, "tax");
+    assert_eq!(tax.value(), 45_000. * 0.3);
+    assert!(std::ptr::eq(ctx.get("income").unwrap(), income));
+    assert!(ctx.get("salary").is_none());
+
+    ctx.bind("income", 30_000.).unwrap();
+    assert_eq!(income.value(), 30_000.);
+    assert_eq!(tax.value(), 25_000. * 0.2);
+    assert!(matches!(
+        &rate.op,
+        OperationType::Select { taken, .. } if taken.get() == crate::Branch::Otherwise
+    ));
+    ctx.bind("deduction", 40_000.).unwrap();
+    assert_eq!(tax.value(), 0.);
+    assert!(ctx.bind("salary", 1.).is_none());
+
+    let graph = tax.as_graphviz(crate::visualization::GraphDirection::DataFlow);
+    assert!(graph.contains("income = 30000"));
+    assert!(tax.as_json().contains("\"income\": 30000.0"));
+    let names: Vec<_> = ctx.inputs().map(|(name, _)| name).collect();
+    assert_eq!(names, ["deduction", "income", "threshold"]);
+}
+
+#[test]
+fn rebind_non_commutative_and_custom() {
+    use crate::Context;
+    let sqrt = Sqrt;
+    let alloc = Arena::new();
+    let mut ctx = Context::new(&alloc);
+    let a = ctx.input("a", 5., "a");
+    let b = ctx.input("b", 2., "b");
+    let c = ctx.input("c", 10., "c");
+    let x = ctx.input("x", 1., "x");
+
+    let difference = c - (a - b);
+    let quotient = x / (a / b);
+    assert_eq!(difference.value(), 7.);
+    ctx.bind("a", 6.).unwrap();
+    assert_eq!(difference.value(), 6.);
+    ctx.bind("b", 3.).unwrap();
+    assert_eq!(quotient.value(), 0.5);
+    assert_eq!(difference.value(), 7.);
+
+    // re-evaluating custom operators works from values, so it doesn't make any nodes
+    let root = sqrt.operate(&[a]);
+    let dependents = a.dependents.borrow().len();
+    let nodes = alloc.len();
+    for value in [4., 9., 16.] {
+        ctx.bind("a", value).unwrap();
+        assert_eq!(root.value(), value.sqrt());
+    }
+    let _ = root.gradient();
+    assert_eq!(alloc.len(), nodes);
+    assert_eq!(a.dependents.borrow().len(), dependents);
+}
+
+#[test]
+fn rebind_through_schedule() {
+    use crate::{Context, Schedule};
+    let brackets = Schedule::marginal("tax", [(0., 0.1, "low"), (100., 0.5, "high")]);
+    let alloc = Arena::new();
+    let mut ctx = Context::new(&alloc);
+    let income = ctx.input("income", 50., "income");
+    let tax = brackets.operate(&[income]);
+    assert_eq!(tax.value(), 5.);
+    ctx.bind("income", 200.);
+    assert_eq!(tax.value(), 10. + 50.);
+
+    // the rows still add up to the value after the input crosses a breakpoint, either way
+    let rows = |op: &Operation| -> Vec<f32> {
+        op.op.history()[1..].iter().map(|row| row.value()).collect()
+    };
+    assert_eq!(rows(tax), [10., 50.]);
+    ctx.bind("income", 40.);
+    assert_eq!(rows(tax), [4., 0.]);
+    assert_eq!(tax.value(), 4.);
+
+    let shipping = Schedule::lookup("shipping", [(0., 5., "small"), (10., 8., "large")]);
+    let weight = ctx.input("weight", 3., "weight");
+    let cost = shipping.operate(&[weight]);
+    ctx.bind("weight", 12.);
+    assert_eq!(rows(cost), [0., 8.]);
+    assert_eq!(cost.value(), 8.);
+    // and the gradient goes through the row that applies now
+    let (_, slope) = tax.gradient()[0];
+    assert!((slope - 0.1).abs() < 1e-3);
+}
+
+#[test]
+fn parse_expressions() {
+    use crate::{ExpressionParser, ParseErrorKind};
+    // operators have to outlive the arena their nodes end up in
+    let sqrt = Sqrt;
+    let total = crate::FnOperator::from_slice_fn("total", None, |xs| xs.iter().sum());
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+    let parser = ExpressionParser::new(&alloc)
+        .variable("income", op_r(50_000., "income"))
+        .variable("rate", op_r(0.2, "rate"))
+        .variable("deduction", op_r(1_000., "deduction"))
+        .function("sqrt", &sqrt)
+        .function("total", &total);
+    let tax = parser.parse("income * rate - deduction").unwrap();
+    assert_eq!(tax.value(), 9_000.);
+    assert!(matches!(&tax.op, OperationType::Difference { history, .. } if history.len() == 2));
+    assert_eq!(parser.parse("-(2 + 2) * -1.5e1").unwrap().value(), 60.);
+    assert_eq!(parser.parse("sqrt(16) / (1 + 1)").unwrap().value(), 2.);
+    assert_eq!(parser.parse("10 - 4 - 3").unwrap().value(), 3.);
+
+    let err = parser.parse("income * salary").unwrap_err();
+    assert_eq!(err.kind, ParseErrorKind::UnknownVariable("salary".into()));
+    assert_eq!(err.span, 9..15);
+    let err = parser.parse("cbrt(8)").unwrap_err();
+    assert_eq!(err.kind, ParseErrorKind::UnknownFunction("cbrt".into()));
+    let err = parser.parse("(income + rate").unwrap_err();
+    assert!(matches!(err.kind, ParseErrorKind::UnexpectedEnd { .. }));
+    assert_eq!(err.span, 14..14);
+    let err = parser.parse("income $ rate").unwrap_err();
+    assert_eq!(err.kind, ParseErrorKind::UnexpectedChar('$'));
+    assert_eq!(err.to_string(), "unexpected character '$' at 7..8");
+    let err = parser.parse("income rate").unwrap_err();
+    assert_eq!(err.span, 7..11);
+
+    // calls with the wrong number of arguments are errors over the whole call, not panics
+    let err = parser.parse("1 + sqrt()").unwrap_err();
+    assert_eq!(
+        err.kind,
+        ParseErrorKind::WrongArgumentCount {
+            function: "sqrt".into(),
+            expected: 1,
+            found: 0
+        }
+    );
+    assert_eq!(err.span, 4..10);
+    let err = parser.parse("sqrt(4, 9)").unwrap_err();
+    assert!(matches!(
+        err.kind,
+        ParseErrorKind::WrongArgumentCount { found: 2, .. }
+    ));
+    let err = parser.parse("total()").unwrap_err();
+    assert_eq!(err.kind, ParseErrorKind::NoArguments("total".into()));
+    assert_eq!(parser.parse("total(1, 2, 3)").unwrap().value(), 6.);
+    // and so are arguments the function can't take
+    let err = parser.parse("2 * sqrt(1 - 5)").unwrap_err();
+    assert_eq!(
+        err.kind,
+        ParseErrorKind::InvalidArgument {
+            function: "sqrt".into(),
+            index: 0,
+            value: -4.,
+            problem: "is negative"
+        }
+    );
+    assert_eq!(err.span, 4..15);
+    assert_eq!(
+        err.to_string(),
+        "argument 0 to `sqrt` is -4, which is negative at 4..15"
+    );
+}
+
+#[test]
+fn load_sources_from_files() {
+    use crate::{parse_sources, Context, Format, LoadError};
+    let alloc = Arena::new();
+    let toml = r#"
+        income = { value = 50000, reason = "gross income", metadata = { source = "W-2" } }
+
+        [loan.rate]
+        value = 0.05
+        reason = "annual rate"
+    "#;
+    let sources = parse_sources(toml, Format::Toml, "inputs.toml", &alloc).unwrap();
+    assert_eq!(sources.keys().collect::<Vec<_>>(), ["income", "loan.rate"]);
+    let rate = sources["loan.rate"];
+    assert_eq!(rate.value(), 0.05);
+    assert!(matches!(&rate.reason, Some(r) if r == "annual rate"));
+    let provenance = rate.provenance().unwrap();
+    assert_eq!(
+        (&*provenance.file, &*provenance.key),
+        ("inputs.toml", "loan.rate")
+    );
+    let income = sources["income"];
+    assert_eq!(
+        income.provenance().unwrap().metadata.as_ref().unwrap()["source"],
+        "W-2"
+    );
+    assert!(income.as_json().contains("\"file\": \"inputs.toml\""));
+
+    let json = r#"{ "rate": { "value": "high", "reason": "oops" } }"#;
+    let err = parse_sources(json, Format::Json, "inputs.json", &alloc).unwrap_err();
+    assert!(matches!(err, LoadError::InvalidEntry { key, .. } if key == "rate"));
+    let json = r#"{ "rate": { "value": 0.1 } }"#;
+    let err = parse_sources(json, Format::Json, "inputs.json", &alloc).unwrap_err();
+    assert_eq!(err.to_string(), "entry `rate` is missing a reason");
+
+    // the process id keeps concurrent test runs out of each other's way
+    let path = std::env::temp_dir().join(format!(
+        "explainability_load_sources_{}.json",
+        std::process::id()
+    ));
+    std::fs::write(&path, r#"{ "rate": { "value": 0.1, "reason": "rate" } }"#).unwrap();
+    let mut ctx = Context::new(&alloc);
+    ctx.load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(ctx.get("rate").unwrap().value(), 0.1);
+    assert!(matches!(
+        ctx.load("inputs.yaml"),
+        Err(LoadError::UnknownFormat(_))
+    ));
+}
+
+#[test]
+fn derive_explainable() {
+    use crate::{Context, Explainable, ExplainedFields};
+
+    #[derive(Explainable)]
+    struct LoanInput {
+        /// amount borrowed
+        principal: f64,
+        /// annual rate,
+        /// before fees
+        rate: f32,
+        years: u32,
+    }
+
+    #[derive(Explainable, Debug, PartialEq)]
+    struct LoanOutput {
+        interest: f64,
+        total: f64,
+    }
+
+    let alloc = Arena::new();
+    let mut ctx = Context::new(&alloc);
+    let input = LoanInput {
+        principal: 1000.,
+        rate: 0.05,
+        years: 2,
+    }
+    .sources(&mut ctx);
+    assert!(matches!(&input.rate.reason, Some(r) if r == "annual rate, before fees"));
+    assert!(matches!(&input.years.reason, Some(r) if r == "years"));
+    assert!(std::ptr::eq(ctx.get("principal").unwrap(), input.principal));
+
+    let interest = input.principal * input.rate * (input.years, "interest");
+    let output = LoanOutputOps {
+        interest,
+        total: input.principal + (interest, "total owed"),
+    };
+    assert_eq!(
+        output.values(),
+        LoanOutput {
+            interest: 100.,
+            total: 1100.
+        }
+    );
+    let json = output.as_json();
+    assert!(json.starts_with("{\n  \"interest\": {"));
+    let graph = output.as_graphviz(crate::GraphDirection::DataFlow);
+    // principal feeds both outputs but only shows up once
+    assert_eq!(graph.matches("principal = 1000").count(), 1);
+    assert!(graph.contains("total owed"));
+}
+
+#[test]
+fn explained_attribute() {
+    use crate::explained;
+
+    #[explained]
+    fn compound(principal: f64, mut rate: f32, years: u32) -> f64 {
+        rate /= 100.0;
+        let growth = 1.0 + rate;
+        let mut balance = principal;
+        for _ in 0..years {
+            balance *= growth;
+        }
+        let fee = -2.5;
+        balance - principal + fee
+    }
+
+    let alloc = Arena::new();
+    let interest = compound(&alloc, 1000., 10., 2);
+    assert!((interest.value() - 207.5).abs() < 0.01);
+    assert!(matches!(&interest.reason, Some(r) if r == "compound"));
+    let graph = interest.as_graphviz(crate::GraphDirection::DataFlow);
+    assert!(graph.contains("compound.principal = 1000"));
+    assert!(graph.contains("compound.rate = 10"));
+    assert!(graph.contains("growth"));
+
+    // the arena argument doesn't clash with the function's own names
+    #[explained]
+    fn doubled(arena: f32) -> f32 {
+        let twice = arena * 2.0;
+        twice
+    }
+    let twice = doubled(&alloc, 4.);
+    assert_eq!(twice.value(), 8.);
+    assert!(matches!(&twice.reason, Some(r) if r == "twice"));
+
+    // integer code and plain floats that don't touch the parameters are left as they are
+    #[explained]
+    fn mixed(rate: f64, years: u32, count: i32) -> f64 {
+        let n = years + 1;
+        let sign = -count;
+        let floor = (sign as f32).max(0.0);
+        let ratio = 1.0 / 4.0;
+        let yearly = rate * 2.0 - ratio;
+        let total = yearly * n as f64;
+        total - floor as f64
+    }
+    let result = mixed(&alloc, 0.5, 3, -2);
+    assert_eq!(result.value(), 0.75 * 4. - 2.);
+    assert!(matches!(&result.reason, Some(r) if r == "mixed"));
+    let graph = result.as_graphviz(crate::GraphDirection::DataFlow);
+    assert!(graph.contains("total") && graph.contains("yearly") && graph.contains("ratio"));
+}
+
+#[test]
+fn explain_and_src_macros() {
+    use crate::{explain, src};
+    let alloc = Arena::new();
+    let arena = &alloc;
+    let gross = 1000.;
+    let rate = 0.25;
+    let gross = src!(gross);
+    let tax = explain!(gross * src!(arena, rate));
+    assert!(matches!(&tax.reason, Some(r) if r == "gross * src!(arena, rate)"));
+    let net = explain!(gross - tax);
+    assert_eq!(net.value(), 750.);
+    assert!(matches!(&net.reason, Some(r) if r == "gross - tax"));
+    assert!(matches!(&gross.reason, Some(r) if r == "gross"));
+    // both macros write the same expression the same way
+    let rates = [0.25];
+    let first = src!(rates[0] * 2.);
+    assert!(matches!(&first.reason, Some(r) if r == "rates[0] * 2."));
+    let doubled = explain!(gross * src!(rates[0] * 2.));
+    assert!(matches!(&doubled.reason, Some(r) if r == "gross * src!(rates[0] * 2.)"));
+    // precedence comes from the expression, so the reason lands on the top level operation
+    let total = explain!(gross + tax * tax);
+    assert_eq!(total.value(), 1000. + 250. * 250.);
+    assert!(matches!(&total.op, OperationType::Sum { .. }));
+}
+
+#[test]
+fn multi_root_export() {
+    use crate::GraphBuilder;
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+    let gross = op_r(1000., "gross pay");
+    let tax = gross * (op_r(0.2, "tax rate"), "income tax");
+    let net = gross - (tax, "net pay");
+    let employer = gross * (op_r(1.1, "overhead"), "employer cost");
+    let graph = GraphBuilder::new().export(&[
+        ("net pay", net),
+        ("total tax", tax),
+        ("employer cost", employer),
+        ("withheld", tax),
+    ]);
+    let dot = graph.to_graphviz();
+    assert_eq!(dot.matches("gross pay").count(), 1);
+    assert!(dot.contains("total tax, withheld: 200"));
+    assert_eq!(dot.matches("[shape=\"box\"]").count(), 3);
+
+    let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+    let nodes = json["nodes"].as_array().unwrap();
+    // 3 roots, gross, tax rate and overhead
+    assert_eq!(nodes.len(), 6);
+    let tax_id = json["outputs"]["withheld"].as_u64().unwrap();
+    assert_eq!(json["outputs"]["total tax"], tax_id);
+    assert_eq!(nodes[tax_id as usize]["kind"], "Product");
+    assert_eq!(
+        nodes[tax_id as usize]["inputs"].as_array().unwrap().len(),
+        2
+    );
+}
+
+#[test]
+fn forward_slice() {
+    use crate::GraphBuilder;
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+    let gross = op_r(1000., "gross pay");
+    let rate = op_r(0.2, "tax rate");
+    let overhead = op_r(1.1, "overhead");
+    let tax = gross * (rate, "income tax");
+    let net = gross - (tax, "net pay");
+    let employer = gross * (overhead, "employer cost");
+    let roots = [("net", net), ("tax", tax), ("employer", employer)];
+
+    let slice = GraphBuilder::new().forward_slice(&roots, &[rate]);
+    assert_eq!(slice.affected_outputs(), ["net", "tax"]);
+    assert_eq!(slice.affected_nodes().len(), 3);
+    let dot = slice.to_graphviz();
+    // rate, tax, net, and the 2 edges between them
+    assert_eq!(dot.matches("[color=\"red\"]").count(), 5);
+
+    let slice = GraphBuilder::new().forward_slice(&roots, &[gross, overhead]);
+    assert_eq!(slice.affected_outputs(), ["net", "tax", "employer"]);
+    assert!(GraphBuilder::new()
+        .export(&roots)
+        .affected_nodes()
+        .is_empty());
+}
+
+#[test]
+fn attribution_methods() {
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-2 * b.abs().max(1.)
+    }
+    let sqrt = Sqrt;
+    let alloc = Arena::new();
+    let (op, op_r) = Operation::make_ctors(&alloc);
+    let hours = op_r(40., "hours");
+    let wage = op_r(30., "wage");
+    let bonus = op_r(100., "bonus");
+    let pay = hours * wage + (bonus, "pay");
+    let inputs = [hours, wage, bonus];
+    let baseline = [0., 0., 0.];
+
+    let gradient = pay.gradient();
+    assert_eq!(gradient.len(), 3);
+    assert!(gradient
+        .iter()
+        .any(|&(s, g)| std::ptr::eq(s, hours) && g == 30.));
+
+    let linear = pay.linearized_attribution(&inputs, &baseline);
+    // the product gets double counted, since linearizing ignores the interaction
+    assert_eq!(linear.contributions[0].amount, 1200.);
+    assert_eq!(linear.baseline_output, 0.);
+
+    let shapley = pay.shapley_attribution(&inputs, &baseline);
+    let total: f32 = shapley.contributions.iter().map(|c| c.amount).sum();
+    assert!(close(total, 1300.));
+    let bonus_share = shapley
+        .contributions
+        .iter()
+        .find(|c| std::ptr::eq(c.source, bonus));
+    assert!(close(bonus_share.unwrap().amount, 100.));
+    assert!(close(shapley.contributions[0].amount, 600.));
+    let sampled = pay.sampled_shapley_attribution(&inputs, &baseline, 50, 7);
+    let total: f32 = sampled.contributions.iter().map(|c| c.amount).sum();
+    assert!(close(total, 1300.));
+
+    let ig = pay.integrated_gradients(&inputs, &baseline, 16);
+    assert!(close(ig.contributions[0].amount, 600.));
+
+    // everything is back where it was afterwards
+    assert_eq!(pay.value(), 1300.);
+    assert_eq!(hours.value(), 40.);
+
+    // custom operators without partials get finite differences
+    let root = sqrt.operate(&[op(16.)]) * (op(2.), "2 sqrt(x)");
+    let (_, g) = root.gradient()[0];
+    assert!(close(g, 0.25));
+
+    let table = shapley.to_string();
+    // ties keep the order the inputs were given in
+    assert!(table.lines().nth(1).unwrap().starts_with("hours"));
+    assert!(table.lines().nth(3).unwrap().starts_with("bonus"));
+    assert!(table.lines().last().unwrap().starts_with("total"));
+    let graph = shapley.as_graphviz(crate::GraphDirection::DataFlow);
+    assert!(graph.contains("\\ncontributes 100"));
+}
+
+#[test]
+fn waterfall_between_scenarios() {
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+    let price = op_r(10., "price");
+    let units = op_r(100., "units");
+    let discount = op_r(50., "discount");
+    let revenue = price * units - (discount, "revenue");
+    let inputs = [price, units, discount];
+
+    let waterfall = revenue.waterfall(&inputs, &[10., 100., 50.], &[12., 90., 80.]);
+    assert_eq!(waterfall.start, 950.);
+    assert_eq!(waterfall.end, 1000.);
+    let deltas: Vec<_> = waterfall.steps.iter().map(|s| s.delta).collect();
+    assert_eq!(deltas, [200., -120., -30.]);
+    // unchanged inputs don't get a step, and the graph is left as it was
+    let same_units = revenue.waterfall(&inputs, &[10., 100., 50.], &[10., 100., 20.]);
+    assert_eq!(same_units.steps.len(), 1);
+    assert_eq!(revenue.value(), 950.);
+
+    let spec: serde_json::Value = serde_json::from_str(&waterfall.to_vega_lite()).unwrap();
+    let values = spec["data"]["values"].as_array().unwrap();
+    assert_eq!(values.len(), 5);
+    assert_eq!(values[1]["label"], "price");
+    assert_eq!(values[2]["kind"], "decrease");
+    assert_eq!(values[4]["to"], 1000.);
+
+    let svg = waterfall.to_svg();
+    assert!(svg.starts_with("<svg xmlns"));
+    assert_eq!(svg.matches("<rect").count(), 5);
+    assert!(svg.contains(">discount</text>"));
+}
+
+#[test]
+fn diff_graphs() {
+    let old_alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&old_alloc);
+    let price = op_r(10., "price");
+    let units = op_r(100., "units");
+    let shipping = op_r(5., "shipping");
+    let old = price * units + (shipping, "total");
+
+    let new_alloc = Arena::new();
+    let (op, op_r) = Operation::make_ctors(&new_alloc);
+    let price = op_r(10., "price");
+    let units = op_r(100., "units");
+    let discount = op_r(50., "discount");
+    // the same inputs, but discounted instead of paying for shipping, with one more step
+    let new = price * units * op(1.) - (discount, "total");
+
+    let changes = crate::diff(old, new);
+    assert!(!changes.is_empty());
+    let added: Vec<_> = changes.added().map(|n| n.new.unwrap().value()).collect();
+    assert_eq!(added, [50., 1.]);
+    let removed: Vec<_> = changes.removed().map(|n| n.old.unwrap().value()).collect();
+    assert_eq!(removed, [5.]);
+    // the product keeps its value but got another input, and the total changed kind
+    let changed: Vec<_> = changes.changed().map(|n| n.old.unwrap().value()).collect();
+    assert_eq!(changed, [1005., 1000.]);
+    // matched by name, so the sources count as unchanged
+    assert_eq!(
+        changes
+            .nodes
+            .iter()
+            .filter(|n| n.change == crate::Change::Unchanged)
+            .count(),
+        2
+    );
+
+    let report = changes.to_string();
+    assert!(report.contains("+ discount = 50"));
+    assert!(report.contains("- shipping = 5"));
+    assert!(report.contains("~ total (-) = 950 (was total (+) = 1005)"));
+    assert!(report.ends_with("2 added, 1 removed, 2 changed"));
+    let dot = changes.to_graphviz();
+    assert!(dot.contains("color=\"orange\""));
+    assert!(dot.contains("color=\"red\""));
+
+    let same = crate::diff(old, old);
+    assert!(same.is_empty());
+    assert_eq!(same.to_string(), "0 added, 0 removed, 0 changed");
+}
+
+#[test]
+fn uncertainty_propagation() {
+    use crate::Uncertainty;
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3 * b.abs().max(1.)
+    }
+    let sqrt = Sqrt;
+    let alloc = Arena::new();
+    let (op, op_r) = Operation::make_ctors(&alloc);
+    let revenue = op_r(1000., "revenue").with_uncertainty(Uncertainty::StdDev(30.));
+    let costs = op_r(600., "costs").with_uncertainty(Uncertainty::Bounds { lo: 500., hi: 700. });
+    let tax_rate = op_r(0.25, "tax rate");
+    let profit = revenue - (costs, "profit");
+    let tax = profit * (tax_rate, "tax");
+
+    let costs_sd = 200. / 12f32.sqrt();
+    assert!(close(costs.uncertainty(), costs_sd));
+    assert!(close(
+        profit.uncertainty(),
+        (900. + costs_sd * costs_sd).sqrt()
+    ));
+    assert!(close(tax.uncertainty(), 0.25 * profit.uncertainty()));
+    assert_eq!(tax_rate.uncertainty(), 0.);
+    // shared inputs cancel out
+    assert_eq!((revenue - (revenue, "nothing")).uncertainty(), 0.);
+    // custom operators without partials go through finite differences
+    let root = sqrt.operate(&[op(16.).with_uncertainty(Uncertainty::StdDev(1.))]);
+    assert!(close(root.uncertainty(), 0.125));
+
+    let contributions = tax.variance_contributions();
+    assert!(std::ptr::eq(contributions[0].0, costs));
+    let total: f32 = contributions.iter().map(|&(_, v)| v).sum();
+    assert!(close(total.sqrt(), tax.uncertainty()));
+
+    let json: serde_json::Value = serde_json::from_str(&tax.as_json()).unwrap();
+    assert!(close(
+        json["std_dev"].as_f64().unwrap() as f32,
+        tax.uncertainty()
+    ));
+    let history = &json["op"]["Product"]["history"];
+    assert_eq!(history[1]["std_dev"], 0.);
+    assert_eq!(
+        history[0]["op"]["Difference"]["history"][0]["op"]["Source"]["uncertainty"]["StdDev"],
+        30.
+    );
+    let dot = tax.as_graphviz(crate::GraphDirection::DataFlow);
+    assert!(dot.contains("1000 +/- 30"));
+    assert!(!dot.contains("0.25 +/-"));
+    let flat = crate::GraphBuilder::new().export(&[("tax", tax)]).to_json();
+    assert!(flat.contains("\"std_dev\""));
+    // nothing changes for exact graphs
+    assert!(!tax_rate.as_json().contains("std_dev"));
+
+    let sources = crate::parse_sources(
+        r#"{ "rate": { "value": 0.05, "reason": "rate", "bounds": [0.04, 0.06] } }"#,
+        crate::Format::Json,
+        "inline",
+        &alloc,
+    )
+    .unwrap();
+    assert_eq!(
+        sources["rate"].declared_uncertainty(),
+        Some(Uncertainty::Bounds { lo: 0.04, hi: 0.06 })
+    );
+}
+
+#[test]
+fn monte_carlo() {
+    use crate::{Distribution, Simulation};
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+    let demand = op_r(100., "demand");
+    let price = op_r(5., "price");
+    let overhead = op_r(50., "overhead");
+    let profit = demand * price - (overhead, "profit");
+
+    let simulation = Simulation::new(profit)
+        .vary(demand, Distribution::Uniform { lo: 80., hi: 120. })
+        .vary(price, Distribution::Empirical(vec![4., 5., 5., 6.]))
+        .vary(
+            overhead,
+            Distribution::Triangular {
+                lo: 40.,
+                mode: 50.,
+                hi: 70.,
+            },
+        )
+        .seed(42);
+    let result = simulation.run(4000);
+    assert_eq!(result.outputs.len(), 4000);
+    // the same seed gives the same draws
+    assert_eq!(simulation.run(4000).outputs, result.outputs);
+    // and the graph is left as it was
+    assert_eq!(profit.value(), 450.);
+
+    assert!((result.mean() - 446.7).abs() < 10.);
+    let (p5, p50, p95) = (
+        result.percentile(5.),
+        result.percentile(50.),
+        result.percentile(95.),
+    );
+    assert!(p5 < p50 && p50 < p95);
+    assert!(result.percentile(0.) <= result.outputs[0]);
+    let draws = &result.inputs[1].1;
+    assert!(draws.iter().all(|p| [4., 5., 6.].contains(p)));
+    let overhead_draws = &result.inputs[2].1;
+    assert!(overhead_draws.iter().all(|&o| (40. ..=70.).contains(&o)));
+
+    let histogram = result.histogram(8);
+    assert_eq!(histogram.counts.iter().sum::<usize>(), 4000);
+    assert_eq!(histogram.lo, result.percentile(0.));
+    assert_eq!(histogram.to_string().lines().count(), 8);
+
+    let correlations = result.correlations();
+    assert!(std::ptr::eq(correlations[0].0, price));
+    assert!(correlations[2].1 < 0.);
+    let summary = result.to_string();
+    assert!(summary.starts_with("4000 runs"));
+    assert!(summary.lines().last().unwrap().ends_with("overhead"));
+
+    let normal = Simulation::new(demand)
+        .vary(
+            demand,
+            Distribution::Normal {
+                mean: 10.,
+                std_dev: 2.,
+            },
+        )
+        .run(5000);
+    assert!((normal.mean() - 10.).abs() < 0.1);
+    assert!((normal.std_dev() - 2.).abs() < 0.1);
+}
+
+#[test]
+fn interval_arithmetic() {
+    use crate::{GraphBuilder, IntervalFlag, Uncertainty};
+    let sqrt = Sqrt;
+    let alloc = Arena::new();
+    let (op, op_r) = Operation::make_ctors(&alloc);
+
+    // exact operations stay points, inexact ones get rounded outward
+    assert_eq!((op(1.) + op(2.)).interval(), Interval::point(3.));
+    let tenth = op(1.) / op(10.);
+    let bounds = tenth.interval();
+    assert!(bounds.lo < bounds.hi);
+    assert!(bounds.lo as f64 <= 0.1 && 0.1 <= bounds.hi as f64);
+
+    // newton's method on exact inputs stays tight around the real square root
+    let target = op_r(2., "target");
+    let guess = newton_sqrt(target, 5, &alloc);
+    let bounds = guess.interval();
+    assert!(bounds.contains(guess.value()));
+    assert!(bounds.lo as f64 <= 2f64.sqrt() && 2f64.sqrt() <= bounds.hi as f64);
+    assert!(bounds.width() < 1e-5);
+
+    // sources with bounds, through products, comparisons, selects and custom operators
+    let width = op_r(3., "width").with_uncertainty(Uncertainty::Bounds { lo: 2., hi: 4. });
+    let area = width * (width, "area");
+    assert_eq!(area.interval(), Interval::new(4., 16.));
+    let side = sqrt.operate(&[area]);
+    assert_eq!(
+        side.interval(),
+        Interval::new(2f32.next_down(), 4f32.next_up())
+    );
+    let big = area.greater_than(op(1.));
+    assert_eq!(big.interval(), Interval::point(1.));
+    let maybe = area.greater_than(op(10.));
+    assert_eq!(maybe.interval(), Interval::new(0., 1.));
+    assert_eq!(
+        Operation::select(maybe, op(5.), op(7.)).interval(),
+        Interval::new(5., 7.)
+    );
+    assert_eq!(
+        width.clamp(op(2.5), op(3.5)).interval(),
+        Interval::new(2.5, 3.5)
+    );
+
+    // dividing by something that might be zero gets flagged
+    let offset = width - (op(3.), "offset");
+    let ratio = op(1.) / (offset, "ratio");
+    let analysis = ratio.interval_analysis();
+    assert_eq!(analysis.get(ratio), Some(Interval::ENTIRE));
+    assert!(std::ptr::eq(analysis.flags()[0].0, ratio));
+    assert_eq!(analysis.flags()[0].1, IntervalFlag::DivisionByZero);
+
+    let graph = GraphBuilder::new()
+        .intervals(true)
+        .export(&[("ratio", ratio)]);
+    let dot = graph.to_graphviz();
+    assert!(dot.contains("in [2, 4]  \\\"width"));
+    assert!(dot.contains("divides by an interval containing 0"));
+    assert!(dot.contains("color=\"red\""));
+    let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+    assert_eq!(json["nodes"][0]["interval_flag"], "DivisionByZero");
+    assert!(!GraphBuilder::new()
+        .export(&[("ratio", ratio)])
+        .to_json()
+        .contains("interval"));
+}
+
+#[test]
+fn precision_diagnostics() {
+    use crate::{Finding, GraphBuilder};
+    let alloc = Arena::new();
+    let (op, _) = Operation::make_ctors(&alloc);
+
+    // exact arithmetic has no error at all
+    let exact = op(1.5) * op(4.) - (op(2.), "exact");
+    let report = exact.precision_report();
+    assert!(report.nodes.iter().all(|n| n.abs_error == 0.));
+    assert_eq!(report.findings().count(), 0);
+
+    // a rounded value, nearly cancelled out
+    let rate = op(1.) / (op(10.), "rate");
+    let scaled = rate * (op(7.), "scaled");
+    let leftover = scaled - (op(0.7), "leftover");
+    let report = leftover.precision_report();
+    let rate_error = report.get(rate).unwrap();
+    assert!(rate_error.abs_error > 0. && rate_error.rel_error < 1e-7);
+    let finding = report.get(leftover).unwrap().finding;
+    assert!(matches!(finding, Some(Finding::Cancellation { bits_lost }) if bits_lost >= 8));
+
+    // a long sum where the rounding on each addition piles up
+    let total: &Operation = (0..10_000).map(|_| op(0.1)).sum();
+    let report = total.precision_report();
+    let sum = report.get(total).unwrap();
+    assert!(matches!(
+        sum.finding,
+        Some(Finding::LongSum { terms: 10_000, .. })
+    ));
+    assert!(sum.rel_error > 1e-5);
+
+    let html = leftover.precision_report().to_html();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert_eq!(html.matches("<tr class=\"finding\">").count(), 1);
+    assert!(html.contains("<td class=\"node\">leftover</td>"));
+
+    let graph = GraphBuilder::new()
+        .precision(true)
+        .export(&[("leftover", leftover)]);
+    let dot = graph.to_graphviz();
+    assert!(dot.contains("cancellation loses"));
+    assert!(dot.contains("[color=\"red\"]"));
+    assert!(graph.to_json().contains("\"Cancellation\""));
+    assert!(!GraphBuilder::new()
+        .export(&[("x", leftover)])
+        .to_graphviz()
+        .contains("error"));
+}
+
+#[test]
+fn non_finite_provenance() {
+    use crate::{Context, NonFiniteCause, StrictMode};
+    let sqrt = Sqrt;
+    let alloc = Arena::new();
+    let (op, op_r) = Operation::make_ctors(&alloc);
+
+    let fine = op(1.) + op(2.);
+    assert!(fine.first_non_finite().is_none());
+
+    let strict = StrictMode::enable();
+    let balance = op_r(-4., "balance");
+    let root = sqrt.operate(&[balance]);
+    let scaled = root * (op_r(2., "scale"), "scaled");
+    let result = scaled + (op_r(1., "offset"), "result");
+    let found = result.first_non_finite().unwrap();
+    assert!(std::ptr::eq(found.origin, root));
+    assert_eq!(found.cause, NonFiniteCause::Operator(" sqrt "));
+    assert_eq!(found.path.len(), 3);
+    assert!(std::ptr::eq(*found.path.last().unwrap(), result));
+    assert!(found.to_string().starts_with("NaN from operator `sqrt` at"));
+    assert!(found.to_string().ends_with("scaled -> result"));
+
+    // the first non-finite input in history order wins
+    let huge = op(3e38) * (op(10.), "huge");
+    let both = huge + (result, "both");
+    assert_eq!(
+        both.first_non_finite().unwrap().cause,
+        NonFiniteCause::Overflow
+    );
+    let nan_source = op_r(f32::NAN, "missing");
+    assert_eq!(
+        (nan_source + op(1.)).first_non_finite().unwrap().cause,
+        NonFiniteCause::Source
+    );
+
+    // strict mode caught each origin as it was made, and nothing downstream of them
+    let causes: Vec<_> = strict.diagnostics().iter().map(|d| d.cause).collect();
+    assert_eq!(
+        causes,
+        [
+            NonFiniteCause::Operator(" sqrt "),
+            NonFiniteCause::Overflow,
+            NonFiniteCause::Source
+        ]
+    );
+    {
+        let nested = StrictMode::enable();
+        let _ = op(0.) / op(0.);
+        assert_eq!(nested.diagnostics().len(), 1);
+        assert_eq!(nested.diagnostics()[0].inputs, [0., 0.]);
+    }
+    assert_eq!(strict.diagnostics().len(), 4);
+
+    // rebinding records nodes that go non-finite, each one once
+    let mut ctx = Context::new(&alloc);
+    let area = ctx.input("area", 4., "floor area");
+    let _ = sqrt.operate(&[area]) * op(4.);
+    let _ = op(100.) / (area, "per unit");
+    assert_eq!(strict.diagnostics().len(), 4);
+    ctx.bind("area", 0.);
+    ctx.bind("area", -1.);
+    let caught = &strict.diagnostics()[4..];
+    assert_eq!(caught.len(), 2);
+    assert_eq!(caught[0].cause, NonFiniteCause::DivisionByZero);
+    assert_eq!(caught[0].reason.as_deref(), Some("per unit"));
+    assert_eq!(caught[1].cause, NonFiniteCause::Operator(" sqrt "));
+    assert_eq!(caught[1].inputs, [-1.]);
+    ctx.bind("area", 9.);
+    assert_eq!(strict.diagnostics().len(), 6);
+    drop(strict);
+    let _ = op(1.) / op(0.);
+    assert!(StrictMode::enable().diagnostics().is_empty());
+}
+
+#[test]
+fn fallible_operators_and_exports() {
+    use crate::{Error, GraphDirection};
+    let sqrt = Sqrt;
+    let alloc = Arena::new();
+    let (op, op_r) = Operation::make_ctors(&alloc);
+
+    let root = sqrt.try_operate(&[op(9.)]).unwrap();
+    assert_eq!(root.value(), 3.);
+    let err = sqrt.try_operate(&[op(1.), op(2.)]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Arity {
+            expected: 1,
+            found: 2,
+            ..
+        }
+    ));
+    assert_eq!(err.to_string(), "`sqrt` takes 1 input(s) but was given 2");
+    let err = sqrt.try_operate(&[op_r(-4., "balance")]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Domain {
+            index: 0,
+            value: -4.,
+            ..
+        }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "input 0 to `sqrt` is -4, which is negative"
+    );
+    // nothing got made for the rejected calls, just the 4 sources and the one root
+    assert_eq!(alloc.len(), 5);
+
+    let total = op_r(2., "a") + (op(3.), "total");
+    let mut json = vec![];
+    total.write_json(&mut json).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        total.try_as_json().unwrap()
+    );
+    let mut dot = vec![];
+    total
+        .write_graphviz(GraphDirection::DataFlow, &mut dot)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(dot).unwrap(),
+        total.as_graphviz(GraphDirection::DataFlow)
+    );
+    // writer errors come back instead of panicking
+    struct Broken;
+    impl std::io::Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    assert!(total.write_json(Broken).is_err());
+    assert!(total
+        .write_graphviz(GraphDirection::DataFlow, Broken)
+        .is_err());
+
+    // other errors convert into the crate error
+    let parse: Error = crate::ExpressionParser::new(&alloc)
+        .parse("1 +")
+        .unwrap_err()
+        .into();
+    assert!(std::error::Error::source(&parse).is_some());
+    // sources just have an empty history now
+    assert!(op(1.).op.history().is_empty());
+}
+
+#[test]
+fn operator_labels_and_parameters() {
+    #[derive(Debug)]
+    struct Pow(f32);
+    impl Operator for Pow {
+        fn symbol(&self) -> &'static str {
+            "pow"
+        }
+        fn label(&self) -> std::borrow::Cow<'_, str> {
+            format!("pow {}", self.0).into()
+        }
+        fn description(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some("raises the input to a fixed power".into())
+        }
+        fn parameters(&self) -> Vec<(&'static str, serde_json::Value)> {
+            vec![("exponent", self.0.into())]
+        }
+        fn evaluate(&self, values: &[f32]) -> f32 {
+            values[0].powf(self.0)
+        }
+        fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+            Operation::evaluated(self, ops)
+        }
+    }
+    let pow = Pow(2.5);
+    let brackets = crate::Schedule::marginal("tax", [(0., 0.1, "low"), (100., 0.2, "high")]);
+    let alloc = Arena::new();
+    let (op, _) = Operation::make_ctors(&alloc);
+
+    let powered = pow.operate(&[op(4.)]);
+    assert_eq!(powered.value(), 32.);
+    assert_eq!(powered.op.variant_symbol(), " pow 2.5 ");
+    let dot = powered.as_graphviz(crate::GraphDirection::DataFlow);
+    assert!(dot.contains("32 pow 2.5"));
+
+    let json: serde_json::Value = serde_json::from_str(&powered.as_json()).unwrap();
+    let operator = &json["op"]["Other"]["op"];
+    assert_eq!(operator["label"], "pow 2.5");
+    assert_eq!(operator["description"], "raises the input to a fixed power");
+    assert_eq!(operator["parameters"]["exponent"], 2.5);
+
+    let tax = brackets.operate(&[op(150.)]);
+    let flat = crate::GraphBuilder::new().export(&[("tax", tax)]).to_json();
+    let flat: serde_json::Value = serde_json::from_str(&flat).unwrap();
+    let schedule = &flat["nodes"][0]["operator"];
+    assert_eq!(schedule["description"], "marginal schedule tax with 2 rows");
+    assert_eq!(
+        schedule["parameters"]["rows"][1],
+        serde_json::json!([100., 0.2f32, "high"])
+    );
+    // operators with nothing extra to say just have their label
+    let row = &flat["nodes"][2]["operator"];
+    assert_eq!(row["label"], "(row)");
+    assert_eq!(row["parameters"]["upper"], 100.);
+}
+
+#[test]
+fn operator_registry_round_trip() {
+    #[derive(Debug)]
+    struct Scale(f32);
+    impl Operator for Scale {
+        fn symbol(&self) -> &'static str {
+            "scale"
+        }
+        fn name(&self) -> Option<&'static str> {
+            Some("scale")
+        }
+        fn parameters(&self) -> Vec<(&'static str, serde_json::Value)> {
+            vec![("factor", self.0.into())]
+        }
+        fn evaluate(&self, values: &[f32]) -> f32 {
+            values[0] * self.0
+        }
+        fn operate<'a>(&'a self, ops: &[&'a Operation<'a>]) -> &'a Operation<'a> {
+            Operation::apply_with_reason(self, ops, self.evaluate(&[ops[0].value()]), "scaled")
+        }
+    }
+    let scale = Scale(3.);
+    let sqrt = Sqrt;
+    let brackets = crate::Schedule::lookup("fee", [(0., 5., "small"), (100., 9., "large")]);
+    let mut registry = crate::OperatorRegistry::new();
+    registry.register("scale", |parameters| {
+        let factor = parameters["factor"]
+            .as_f64()
+            .ok_or("`factor` isn't a number")?;
+        Ok(Box::new(Scale(factor as f32)))
+    });
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+
+    let amount = op_r(120., "amount");
+    let limit = op_r(50., "limit");
+    let scaled = scale.operate(&[amount]);
+    let fee = brackets.operate(&[amount]);
+    let capped = Operation::select(amount.greater_than(limit), limit, scaled);
+    let total = capped.max(fee) - op_r(1., "discount") / op_r(2., "split");
+    assert_eq!(total.value(), 49.5);
+    let json = crate::GraphBuilder::new()
+        .export(&[("total", total), ("fee", fee)])
+        .to_json();
+    let flat: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert!(flat["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|node| node["operator"]["name"] == "scale"
+            && node["operator"]["parameters"]["factor"] == 3.));
+
+    let rebuilt_alloc = Arena::new();
+    let outputs = registry.rebuild(&json, &rebuilt_alloc).unwrap();
+    assert_eq!(outputs["total"].value(), 49.5);
+    assert_eq!(outputs["fee"].value(), 9.);
+    assert!(matches!(&outputs["fee"].reason, Some(r) if r == "fee"));
+    assert_eq!(
+        crate::diff(total, outputs["total"]).to_string(),
+        crate::diff(total, total).to_string()
+    );
+
+    // an operator that isn't registered, or can't say what it's registered as, stops the rebuild
+    let empty_alloc = Arena::new();
+    let unregistered = json.replace("\"scale\"", "\"stretch\"");
+    assert!(matches!(
+        registry.rebuild(&unregistered, &empty_alloc),
+        Err(crate::Error::UnknownOperator(name)) if name == "stretch"
+    ));
+    let json = crate::GraphBuilder::new()
+        .export(&[("root", sqrt.operate(&[amount]))])
+        .to_json();
+    assert!(matches!(
+        registry.rebuild(&json, &empty_alloc),
+        Err(crate::Error::InvalidGraph { .. })
+    ));
+    assert!(matches!(
+        registry.build("schedule row", &serde_json::Map::new()),
+        Err(crate::Error::InvalidParameters { .. })
+    ));
+
+    // so does a node with the wrong number of inputs for its operator, rather than a panic
+    let mut malformed = flat.clone();
+    for node in malformed["nodes"].as_array_mut().unwrap() {
+        if node["operator"]["name"] == "schedule row" {
+            node["inputs"] = serde_json::json!([]);
+        }
+    }
+    assert!(matches!(
+        registry.rebuild(&malformed.to_string(), &empty_alloc),
+        Err(crate::Error::Arity {
+            expected: 1,
+            found: 0,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn closure_operators() {
+    let square = crate::FnOperator::from_fn("square", |x| x * x);
+    let mean = crate::FnOperator::from_slice_fn("mean", None, |xs| {
+        xs.iter().sum::<f32>() / xs.len() as f32
+    });
+    let mut registry = crate::OperatorRegistry::new();
+    registry.register("square", |_| {
+        Ok(Box::new(crate::FnOperator::from_fn("square", |x| x * x)))
+    });
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+
+    let x = op_r(3., "x");
+    let squared = square.operate(&[x]);
+    let averaged = mean.operate(&[squared, op_r(1., "y"), op_r(2., "z")]);
+    assert_eq!(averaged.value(), 4.);
+    assert_eq!(averaged.op.history().len(), 3);
+    assert_eq!(squared.op.variant_symbol(), " square ");
+    assert!(matches!(
+        square.try_operate(&[x, x]),
+        Err(crate::Error::Arity {
+            expected: 1,
+            found: 2,
+            ..
+        })
+    ));
+    // no partials given, so they're estimated through reevaluate
+    let (_, slope) = squared.gradient()[0];
+    assert!((slope - 6.).abs() < 0.05);
+
+    // its symbol doubles as its registered name
+    let json = crate::GraphBuilder::new()
+        .export(&[("squared", squared)])
+        .to_json();
+    let rebuilt_alloc = Arena::new();
+    let outputs = registry.rebuild(&json, &rebuilt_alloc).unwrap();
+    assert_eq!(outputs["squared"].value(), 9.);
+}
+
+#[test]
+fn composite_operators() {
+    fn weighted_average() -> crate::Composite {
+        crate::Composite::new("weighted average", Some(4), |_, ops| {
+            let total = ops[0] * ops[1] + ops[2] * (ops[3], "weighted total");
+            total / (ops[1] + (ops[3], "total weight"))
+        })
+    }
+    let average = weighted_average();
+    let mut registry = crate::OperatorRegistry::new();
+    registry.register("weighted average", |_| Ok(Box::new(weighted_average())));
+    let alloc = Arena::new();
+    let (_, op_r) = Operation::make_ctors(&alloc);
+
+    let (a, b) = (op_r(10., "a"), op_r(20., "b"));
+    let result = average.operate(&[a, op_r(1., "a weight"), b, op_r(3., "b weight")]);
+    assert_eq!(result.value(), 17.5);
+    // the inner graph is still there for the analyses to see through
+    assert_eq!(result.sources().len(), 4);
+    let gradient = result.gradient();
+    assert!(std::ptr::eq(gradient[0].0, a) && gradient[0].1 == 0.25);
+    assert!(std::ptr::eq(gradient[2].0, b) && gradient[2].1 == 0.75);
+    assert_eq!(result.interval(), crate::Interval::point(17.5));
+
+    let collapsed = result.as_graphviz(crate::GraphDirection::DataFlow);
+    assert!(collapsed.contains("box3d"));
+    assert!(collapsed.contains("weighted average"));
+    assert!(!collapsed.contains("weighted total"));
+    let expand = crate::GraphBuilder::new().expand_composites(true);
+    let expanded = result.as_graphviz_with(&expand);
+    assert!(expanded.contains("weighted total") && !expanded.contains("box3d"));
+
+    let json: serde_json::Value = serde_json::from_str(&result.as_json()).unwrap();
+    assert_eq!(json["op"]["Other"]["collapsed"], true);
+    assert_eq!(json["op"]["Other"]["history"].as_array().unwrap().len(), 4);
+    let json: serde_json::Value = serde_json::from_str(&result.as_json_with(&expand)).unwrap();
+    assert_eq!(json["op"]["Other"]["history"].as_array().unwrap().len(), 5);
+
+    // both ways of exporting rebuild to the same thing
+    for builder in [crate::GraphBuilder::new(), expand] {
+        let flat = builder.export(&[("average", result)]).to_json();
+        let rebuilt_alloc = Arena::new();
+        let outputs = registry.rebuild(&flat, &rebuilt_alloc).unwrap();
+        assert_eq!(outputs["average"].value(), 17.5);
+        assert!(crate::diff(result, outputs["average"]).is_empty());
+    }
+
+    // a collapsed composite gets its arity checked before it's built again
+    let flat = crate::GraphBuilder::new()
+        .export(&[("average", result)])
+        .to_json();
+    let mut malformed: serde_json::Value = serde_json::from_str(&flat).unwrap();
+    for node in malformed["nodes"].as_array_mut().unwrap() {
+        if node["collapsed"] == true {
+            node["inputs"].as_array_mut().unwrap().pop();
+        }
+    }
+    let rebuilt_alloc = Arena::new();
+    assert!(matches!(
+        registry.rebuild(&malformed.to_string(), &rebuilt_alloc),
+        Err(crate::Error::Arity {
+            expected: 4,
+            found: 3,
+            ..
+        })
+    ));
 }
@@ -0,0 +1,172 @@
+//! First-order uncertainty propagation. Sources can say how sure we are of them, as a standard
+//! deviation or as bounds, and every node computed from them gets a standard deviation too.
+//!
+//! The propagation is linearized: each node's deviation is its sensitivity to each uncertain
+//! source times that source's deviation, with the sources treated as independent. Sensitivities
+//! come from the same partial derivatives as [`Operation::gradient`], so custom operators can
+//! take part by implementing [`crate::Operator::partials`]. Shared inputs are accounted for, so
+//! `x - x` comes out exact however unsure `x` is.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{Num, Operation, OperationType};
+
+/// How unsure we are of a source's value
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Uncertainty {
+    /// one standard deviation
+    StdDev(Num),
+    /// the value is somewhere in `[lo, hi]`, with anywhere in between as likely as anywhere else
+    Bounds { lo: Num, hi: Num },
+}
+
+impl Uncertainty {
+    /// the standard deviation this amounts to. Bounds are taken as a uniform distribution
+    pub fn std_dev(&self) -> Num {
+        match *self {
+            Uncertainty::StdDev(std_dev) => std_dev.abs(),
+            Uncertainty::Bounds { lo, hi } => (hi - lo).abs() / (12 as Num).sqrt(),
+        }
+    }
+}
+
+/// Every node's sensitivity to every uncertain source, scaled by that source's deviation
+pub(crate) struct Propagation<'a> {
+    sources: Vec<&'a Operation<'a>>,
+    terms: HashMap<*const Operation<'a>, Vec<Num>>,
+}
+
+impl<'a> Propagation<'a> {
+    /// `None` when nothing under `roots` is uncertain
+    pub(crate) fn new(roots: &[&'a Operation<'a>]) -> Option<Self> {
+        let order: Vec<_> = roots
+            .iter()
+            .flat_map(|root| root.topological_order())
+            .collect();
+        let mut sources: Vec<&'a Operation<'a>> = vec![];
+        for &node in &order {
+            if node.declared_uncertainty().is_some()
+                && !sources.iter().any(|&s| std::ptr::eq(s, node))
+            {
+                sources.push(node);
+            }
+        }
+        if sources.is_empty() {
+            return None;
+        }
+        let mut terms: HashMap<*const Operation<'a>, Vec<Num>> = HashMap::new();
+        for node in order {
+            if terms.contains_key(&(node as *const _)) {
+                continue;
+            }
+            let mut own = vec![0.; sources.len()];
+            match &node.op {
+                OperationType::Source { uncertainty, .. } => {
+                    if let Some(uncertainty) = uncertainty.get() {
+                        let idx = sources.iter().position(|&s| std::ptr::eq(s, node));
+                        own[idx.unwrap()] = uncertainty.std_dev();
+                    }
+                }
+                op => {
+                    for (prior, partial) in op.history().iter().zip(op.local_partials()) {
+                        if partial == 0. {
+                            continue;
+                        }
+                        for (term, prior_term) in own.iter_mut().zip(&terms[&(*prior as *const _)])
+                        {
+                            *term += partial * prior_term;
+                        }
+                    }
+                }
+            }
+            terms.insert(node, own);
+        }
+        Some(Propagation { sources, terms })
+    }
+
+    /// `op`'s standard deviation, zero if it's exact or wasn't under the roots
+    pub(crate) fn std_dev(&self, op: &Operation<'a>) -> Num {
+        self.terms
+            .get(&(op as *const _))
+            .map_or(0., |terms| terms.iter().map(|t| t * t).sum::<Num>().sqrt())
+    }
+
+    /// adds each node's standard deviation to its JSON, as nested by [`Operation::as_json`]
+    pub(crate) fn annotate_json(&self, json: &mut Value, op: &'a Operation<'a>) {
+        let Value::Object(fields) = json else { return };
+        fields.insert("std_dev".into(), self.std_dev(op).into());
+        if matches!(op.op, OperationType::Source { .. }) {
+            return;
+        }
+        let history = fields
+            .get_mut("op")
+            .and_then(|variant| variant.get_mut(op.op.kind_name()))
+            .and_then(|variant| variant.get_mut("history"));
+        if let Some(Value::Array(entries)) = history {
+            for (entry, &prior) in entries.iter_mut().zip(op.op.history()) {
+                self.annotate_json(entry, prior);
+            }
+        }
+    }
+}
+
+impl<'a> Operation<'a> {
+    /// marks a source as uncertain, for use in [`Operation::uncertainty`] and everything
+    /// computed from it
+    /// ```
+    ///# use explainability_rs::{Operation, OpArena, Uncertainty};
+    /// let arena = OpArena::new();
+    /// let (_, op_r) = Operation::make_ctors(&arena);
+    /// let width = op_r(3., "width").with_uncertainty(Uncertainty::StdDev(0.3));
+    /// let height = op_r(4., "height").with_uncertainty(Uncertainty::StdDev(0.4));
+    /// let area = width * (height, "area");
+    /// // each input is 10% off, so the area is about 14% off
+    /// assert!((area.uncertainty() - 12. * 0.02f32.sqrt()).abs() < 1e-4);
+    /// ```
+    ///
+    /// # Panics
+    /// if this isn't a source
+    pub fn with_uncertainty(&'a self, uncertainty: Uncertainty) -> &'a Self {
+        match &self.op {
+            OperationType::Source {
+                uncertainty: declared,
+                ..
+            } => declared.set(Some(uncertainty)),
+            _ => panic!("only sources can be given an uncertainty"),
+        }
+        self
+    }
+
+    /// the uncertainty given to this source, if any
+    pub fn declared_uncertainty(&self) -> Option<Uncertainty> {
+        match &self.op {
+            OperationType::Source { uncertainty, .. } => uncertainty.get(),
+            _ => None,
+        }
+    }
+
+    /// the standard deviation of this value, propagated from the uncertain sources it was
+    /// computed from. Zero if none of them are
+    pub fn uncertainty(&'a self) -> Num {
+        Propagation::new(&[self]).map_or(0., |propagation| propagation.std_dev(self))
+    }
+
+    /// how much of this value's variance comes from each uncertain source, largest first. These
+    /// add up to the square of [`Operation::uncertainty`]
+    pub fn variance_contributions(&'a self) -> Vec<(&'a Operation<'a>, Num)> {
+        let Some(propagation) = Propagation::new(&[self]) else {
+            return vec![];
+        };
+        let mut contributions: Vec<_> = propagation
+            .sources
+            .iter()
+            .zip(&propagation.terms[&(self as *const _)])
+            .map(|(&source, term)| (source, term * term))
+            .collect();
+        contributions.sort_by(|a, b| b.1.total_cmp(&a.1));
+        contributions
+    }
+}
@@ -1,7 +1,11 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap};
 
 use dot::{Edges, GraphWalk, Labeller, Nodes};
 
+use crate::interval::{IntervalAnalysis, IntervalFlag};
+use crate::precision::{Finding, NodeError, PrecisionReport};
+use crate::uncertainty::Propagation;
+use crate::Num;
 use crate::Operation;
 use crate::OperationType;
 
@@ -11,43 +15,243 @@ pub enum GraphDirection {
     DataFlow,
 }
 
+/// How an edge should be drawn, depending on what role its source played for its target
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum EdgeRole {
+    Plain,
+    /// the candidate a `Select` didn't go with
+    Untaken,
+    /// the candidate a `Choose` went with, and by how much it won
+    Picked {
+        margin: Num,
+    },
+}
+
+/// Renders several named outputs as one graph, so that the inputs and intermediate results they
+/// share only show up once.
+/// ```
+///# use explainability_rs::{GraphBuilder, Operation, OpArena};
+/// let arena = OpArena::new();
+/// let (_, op_r) = Operation::make_ctors(&arena);
+/// let gross = op_r(1000., "gross pay");
+/// let tax = gross * (op_r(0.2, "tax rate"), "income tax");
+/// let net = gross - (tax, "net pay");
+/// let graph = GraphBuilder::new().export(&[("net pay", net), ("total tax", tax)]);
+/// let dot = graph.to_graphviz();
+/// assert_eq!(dot.matches("gross pay").count(), 1);
+/// ```
+#[derive(Clone, Copy)]
+pub struct GraphBuilder {
+    direction: GraphDirection,
+    intervals: bool,
+    precision: bool,
+    pub(crate) expand_composites: bool,
+}
+
+impl Default for GraphBuilder {
+    fn default() -> Self {
+        GraphBuilder {
+            direction: GraphDirection::DataFlow,
+            intervals: false,
+            precision: false,
+            expand_composites: false,
+        }
+    }
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// which way the edges point, data flow by default
+    pub fn direction(mut self, direction: GraphDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// whether to show each node's [`Operation::interval`] next to its value, and flag the
+    /// nodes the interval analysis flagged. Off by default
+    pub fn intervals(mut self, intervals: bool) -> Self {
+        self.intervals = intervals;
+        self
+    }
+
+    /// whether to show each node's floating point error from [`Operation::precision_report`],
+    /// and highlight its findings. Off by default
+    pub fn precision(mut self, precision: bool) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// whether to show the inner graph of each [`crate::Composite`]. Off by default, which draws
+    /// a composite as a single node fed by its inputs
+    pub fn expand_composites(mut self, expand: bool) -> Self {
+        self.expand_composites = expand;
+        self
+    }
+
+    /// one graph covering every root, with each root labeled by its name. A node that's several
+    /// outputs at once gets all their names
+    pub fn export<'a>(&self, roots: &[(&str, &'a Operation<'a>)]) -> OperationGraph<'a> {
+        let ops: Vec<_> = roots.iter().map(|&(_, op)| op).collect();
+        let mut graph = self.graph(&ops);
+        for &(name, op) in roots {
+            if let Some(idx) = graph.index_of(op) {
+                graph.outputs.entry(idx).or_default().push(name.to_owned());
+            }
+        }
+        graph
+    }
+
+    /// [`GraphBuilder::export`] without naming the roots
+    pub(crate) fn graph<'a>(&self, roots: &[&'a Operation<'a>]) -> OperationGraph<'a> {
+        let mut graph = OperationGraph::from_roots(roots, self.direction, self.expand_composites);
+        if self.intervals {
+            graph.intervals = Some(IntervalAnalysis::new(roots));
+        }
+        if self.precision {
+            graph.precision = Some(PrecisionReport::new(roots));
+        }
+        graph
+    }
+
+    /// the graph of `roots`, with everything that depends on any of `changed` highlighted and
+    /// everything else faded. This answers "if these sources change, which outputs move?"
+    /// ```
+    ///# use explainability_rs::{GraphBuilder, Operation, OpArena};
+    /// let arena = OpArena::new();
+    /// let (_, op_r) = Operation::make_ctors(&arena);
+    /// let gross = op_r(1000., "gross pay");
+    /// let rate = op_r(0.2, "tax rate");
+    /// let tax = gross * (rate, "income tax");
+    /// let overhead = gross * (op_r(1.1, "overhead"), "employer cost");
+    /// let slice = GraphBuilder::new().forward_slice(&[("tax", tax), ("cost", overhead)], &[rate]);
+    /// assert_eq!(slice.affected_outputs(), ["tax"]);
+    /// ```
+    pub fn forward_slice<'a>(
+        &self,
+        roots: &[(&str, &'a Operation<'a>)],
+        changed: &[&'a Operation<'a>],
+    ) -> OperationGraph<'a> {
+        let mut graph = self.export(roots);
+        let mut dependents = vec![vec![]; graph.nodes.len()];
+        for (idx, inputs) in graph.inputs.iter().enumerate() {
+            for &input in inputs {
+                dependents[input].push(idx);
+            }
+        }
+        let mut affected = vec![false; graph.nodes.len()];
+        let mut stack: Vec<usize> = changed
+            .iter()
+            .filter_map(|&op| graph.index_of(op))
+            .collect();
+        while let Some(idx) = stack.pop() {
+            if !std::mem::replace(&mut affected[idx], true) {
+                stack.extend(&dependents[idx]);
+            }
+        }
+        graph.affected = Some(affected);
+        graph
+    }
+}
+
 #[derive(Default)]
 pub struct OperationGraph<'a> {
-    nodes: Vec<&'a Operation<'a>>,
+    pub(crate) nodes: Vec<&'a Operation<'a>>,
+    /// where each node is in `nodes`
+    index: HashMap<*const Operation<'a>, usize>,
     edges: Vec<(usize, usize)>,
+    /// for each node, the indices of the nodes in its history
+    pub(crate) inputs: Vec<Vec<usize>>,
+    /// output names for the roots that have them
+    outputs: HashMap<usize, Vec<String>>,
+    /// nodes only reachable from the roots through a branch a `Select` didn't take
+    faded_nodes: Vec<bool>,
+    /// composites drawn as a single node, with their inner graph left out
+    collapsed: Vec<bool>,
+    /// anything that isn't `EdgeRole::Plain`
+    edge_roles: HashMap<(usize, usize), EdgeRole>,
+    /// for forward slices, which nodes depend on the changed sources
+    affected: Option<Vec<bool>>,
+    /// extra lines for node labels
+    annotations: HashMap<usize, Vec<String>>,
+    /// each node's standard deviation, when any of the sources are uncertain
+    std_devs: Option<Vec<Num>>,
+    /// when asked for, every node's interval
+    intervals: Option<IntervalAnalysis<'a>>,
+    /// when asked for, every node's floating point error
+    precision: Option<PrecisionReport<'a>>,
 }
 
 impl<'a> OperationGraph<'a> {
-    pub(crate) fn from_op(
-        mut op: &'a Operation<'a>,
+    /// the graph of `op` with composites collapsed, the way it's drawn by default
+    pub(crate) fn from_op(op: &'a Operation<'a>, direction: GraphDirection) -> OperationGraph<'a> {
+        Self::from_roots(&[op], direction, false)
+    }
+
+    /// one graph covering the histories of all of `roots`, with the nodes they share included
+    /// only once. Unless `expand_composites`, a composite's history stops short of its inner
+    /// graph
+    pub(crate) fn from_roots(
+        roots: &[&'a Operation<'a>],
         direction: GraphDirection,
+        expand_composites: bool,
     ) -> OperationGraph<'a> {
-        let mut nodes = Vec::with_capacity(op._allocator.len());
-        nodes.push(op);
-        let mut edges = Vec::with_capacity(op._allocator.len());
+        let Some(&first) = roots.first() else {
+            return OperationGraph::default();
+        };
+        let mut nodes: Vec<&'a Operation<'a>> = Vec::with_capacity(first.arena().len());
+        let mut index: HashMap<*const Operation<'a>, usize> = HashMap::new();
+        for &root in roots {
+            index.entry(root).or_insert_with(|| {
+                nodes.push(root);
+                nodes.len() - 1
+            });
+        }
+        let root_count = nodes.len();
+        let mut op = first;
+        let mut edges = Vec::with_capacity(first.arena().len());
+        let mut edge_roles = HashMap::new();
+        // (child, untaken) pairs per node, used afterwards to work out what's still live
+        let mut children: Vec<Vec<(usize, bool)>> = vec![];
+        let mut collapsed = vec![];
         let mut current_parent: usize = 0;
         use OperationType::*;
         loop {
+            let history = match &op.op {
+                Other { op, history, .. } if op.is_composite() && !expand_composites => {
+                    collapsed.push(true);
+                    &history[..history.len().saturating_sub(1)]
+                }
+                node => {
+                    collapsed.push(false);
+                    node.history()
+                }
+            };
             match &op.op {
-                Source { .. } => {}
+                Source { .. } => children.push(vec![]),
                 node => {
-                    for &prior in node.history() {
-                        let position = nodes
-                            .iter()
-                            .enumerate()
-                            .find(|(_, &i_op)| std::ptr::eq(i_op, prior))
-                            .map(|(idx, _)| idx)
-                            .unwrap_or_else(|| {
-                                nodes.push(prior);
-                                nodes.len() - 1
-                            });
+                    let mut node_children = vec![];
+                    for &prior in history {
+                        let position = *index.entry(prior).or_insert_with(|| {
+                            nodes.push(prior);
+                            nodes.len() - 1
+                        });
                         // edges are in data feed direction
-                        edges.push(if direction == GraphDirection::DataFlow {
+                        let edge = if direction == GraphDirection::DataFlow {
                             (position, current_parent)
                         } else {
                             (current_parent, position)
-                        });
+                        };
+                        let role = node.edge_role(prior);
+                        if role != EdgeRole::Plain {
+                            edge_roles.insert(edge, role);
+                        }
+                        node_children.push((position, role == EdgeRole::Untaken));
+                        edges.push(edge);
                     }
+                    children.push(node_children);
                 }
             };
             current_parent += 1;
@@ -58,7 +262,39 @@ impl<'a> OperationGraph<'a> {
         }
         edges.sort();
         edges.dedup();
-        OperationGraph { nodes, edges }
+        let mut faded_nodes = vec![true; nodes.len()];
+        let mut stack: Vec<usize> = (0..root_count).collect();
+        while let Some(idx) = stack.pop() {
+            if std::mem::replace(&mut faded_nodes[idx], false) {
+                stack.extend(
+                    children[idx]
+                        .iter()
+                        .filter(|(_, untaken)| !untaken)
+                        .map(|&(child, _)| child),
+                );
+            }
+        }
+        let std_devs = Propagation::new(roots)
+            .map(|propagation| nodes.iter().map(|&n| propagation.std_dev(n)).collect());
+        let inputs = children
+            .into_iter()
+            .map(|node_children| node_children.into_iter().map(|(idx, _)| idx).collect())
+            .collect();
+        OperationGraph {
+            nodes,
+            index,
+            edges,
+            inputs,
+            outputs: HashMap::new(),
+            faded_nodes,
+            collapsed,
+            edge_roles,
+            affected: None,
+            annotations: HashMap::new(),
+            std_devs,
+            intervals: None,
+            precision: None,
+        }
     }
 }
 
@@ -94,13 +330,102 @@ where
     fn node_label(&'b self, n: &&'b Operation<'a>) -> dot::LabelText<'b> {
         let n = *n;
         let variant = n.op.variant_symbol();
-        let value = n.op.value();
+        let value = match &n.op {
+            OperationType::Compare { value, .. } => (value.get() != 0.).to_string(),
+            OperationType::Source {
+                value,
+                name: Some(name),
+                ..
+            } => format!("{name} = {}", value.get()),
+            op => op.value().to_string(),
+        };
+        let value = match self.std_dev(n) {
+            Some(std_dev) if std_dev > 0. => format!("{value} +/- {std_dev}"),
+            _ => value,
+        };
+        let value = match self.intervals.as_ref().and_then(|analysis| analysis.get(n)) {
+            Some(interval) => format!("{value} in {interval}"),
+            None => value,
+        };
         let reason = n
             .reason
             .as_ref()
-            .map(|r| format!(" \"{r}\""))
-            .unwrap_or_default();
-        dot::LabelText::label(format!("{value}{variant}{reason}"))
+            .map_or_else(String::new, |r| format!(" \"{r}\""));
+        let output = self
+            .index_of(n)
+            .and_then(|idx| self.outputs.get(&idx))
+            .map_or_else(String::new, |names| format!("{}: ", names.join(", ")));
+        let notes: String = self
+            .index_of(n)
+            .and_then(|idx| self.annotations.get(&idx))
+            .into_iter()
+            .flatten()
+            .map(|note| format!("\n{note}"))
+            .chain(self.interval_flag(n).map(|flag| format!("\n{flag}")))
+            .chain(self.node_error(n).map(|error| {
+                format!(
+                    "\nerror {:.1e}, relative {:.1e}",
+                    error.abs_error, error.rel_error
+                )
+            }))
+            .chain(self.finding(n).map(|finding| format!("\n{finding}")))
+            .collect();
+        dot::LabelText::label(format!("{output}{value}{variant}{reason}{notes}"))
+    }
+    fn node_shape(&'b self, n: &&'b Operation<'a>) -> Option<dot::LabelText<'b>> {
+        let idx = self.index_of(n)?;
+        if self.collapsed[idx] {
+            Some(dot::LabelText::label("box3d"))
+        } else {
+            self.outputs
+                .contains_key(&idx)
+                .then(|| dot::LabelText::label("box"))
+        }
+    }
+    fn node_style(&'b self, n: &&'b Operation<'a>) -> dot::Style {
+        match self.index_of(n).and_then(|idx| self.is_affected(idx)) {
+            Some(true) => dot::Style::Bold,
+            _ if self.is_flagged(n) => dot::Style::Bold,
+            _ if self.is_faded(n) => dot::Style::Dashed,
+            _ => dot::Style::None,
+        }
+    }
+    fn node_color(&'b self, n: &&'b Operation<'a>) -> Option<dot::LabelText<'b>> {
+        match self.index_of(n).and_then(|idx| self.is_affected(idx)) {
+            Some(true) => Some(dot::LabelText::label("red")),
+            Some(false) => Some(dot::LabelText::label("gray")),
+            None if self.is_flagged(n) => Some(dot::LabelText::label("red")),
+            None => self.is_faded(n).then(|| dot::LabelText::label("gray")),
+        }
+    }
+    fn edge_label(&'b self, e: &(usize, usize)) -> dot::LabelText<'b> {
+        match self.edge_roles.get(e) {
+            Some(EdgeRole::Picked { margin }) => {
+                dot::LabelText::label(format!("picked by {margin}"))
+            }
+            _ => dot::LabelText::label(""),
+        }
+    }
+    fn edge_style(&'b self, e: &(usize, usize)) -> dot::Style {
+        if self.is_affected(e.0).is_some() && self.is_affected_edge(e) {
+            return dot::Style::Bold;
+        }
+        match self.edge_roles.get(e) {
+            Some(EdgeRole::Untaken) => dot::Style::Dashed,
+            Some(EdgeRole::Picked { .. }) => dot::Style::Bold,
+            _ => dot::Style::None,
+        }
+    }
+    fn edge_color(&'b self, e: &(usize, usize)) -> Option<dot::LabelText<'b>> {
+        match self.is_affected(e.0) {
+            Some(_) if self.is_affected_edge(e) => return Some(dot::LabelText::label("red")),
+            Some(_) => return Some(dot::LabelText::label("gray")),
+            None => {}
+        }
+        match self.edge_roles.get(e) {
+            Some(EdgeRole::Untaken) => Some(dot::LabelText::label("gray")),
+            _ => None,
+        }
     }
 }
 
@@ -108,9 +433,182 @@ impl<'a, 'b> OperationGraph<'a>
 where
     'a: 'b,
 {
-    pub(crate) fn to_graphviz(&'b self) -> String {
+    fn index_of(&self, n: &Operation<'a>) -> Option<usize> {
+        self.index.get(&(n as *const _)).copied()
+    }
+
+    fn std_dev(&self, n: &Operation<'a>) -> Option<Num> {
+        let idx = self.index_of(n)?;
+        self.std_devs.as_ref().map(|std_devs| std_devs[idx])
+    }
+
+    fn interval_flag(&self, n: &Operation<'a>) -> Option<IntervalFlag> {
+        self.intervals.as_ref()?.flag(n)
+    }
+
+    fn node_error(&self, n: &Operation<'a>) -> Option<&NodeError<'a>> {
+        self.precision.as_ref()?.get(n)
+    }
+
+    fn finding(&self, n: &Operation<'a>) -> Option<Finding> {
+        self.node_error(n)?.finding
+    }
+
+    /// whether an analysis turned up something about this node
+    fn is_flagged(&self, n: &Operation<'a>) -> bool {
+        self.interval_flag(n).is_some() || self.finding(n).is_some()
+    }
+
+    fn is_faded(&self, n: &Operation<'a>) -> bool {
+        self.index_of(n).is_some_and(|idx| self.faded_nodes[idx])
+    }
+
+    /// adds a line to `op`'s label, if it's in the graph
+    pub fn annotate(&mut self, op: &Operation<'a>, note: impl Into<String>) {
+        if let Some(idx) = self.index_of(op) {
+            self.annotations.entry(idx).or_default().push(note.into());
+        }
+    }
+
+    /// `None` when this isn't a forward slice
+    fn is_affected(&self, idx: usize) -> Option<bool> {
+        self.affected.as_ref().map(|affected| affected[idx])
+    }
+
+    fn is_affected_edge(&self, &(a, b): &(usize, usize)) -> bool {
+        self.is_affected(a) == Some(true) && self.is_affected(b) == Some(true)
+    }
+
+    /// for forward slices, every node that depends on the changed sources, the sources
+    /// included. Empty for any other graph
+    pub fn affected_nodes(&self) -> Vec<&'a Operation<'a>> {
+        (0..self.nodes.len())
+            .filter(|&idx| self.is_affected(idx) == Some(true))
+            .map(|idx| self.nodes[idx])
+            .collect()
+    }
+
+    /// for forward slices, the names of the outputs that depend on the changed sources, in the
+    /// order the outputs were given. Empty for any other graph
+    pub fn affected_outputs(&self) -> Vec<&str> {
+        let mut outputs: Vec<_> = self
+            .outputs
+            .iter()
+            .filter(|&(&idx, _)| self.is_affected(idx) == Some(true))
+            .flat_map(|(&idx, names)| names.iter().map(move |name| (idx, name.as_str())))
+            .collect();
+        outputs.sort_by_key(|&(idx, _)| idx);
+        outputs.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// the graph in dot format, which can be rendered with GraphViz
+    pub fn to_graphviz(&'b self) -> String {
         let mut writer = vec![];
-        dot::render(self, &mut writer).unwrap();
-        String::from_utf8(writer).unwrap()
+        self.write_graphviz(&mut writer)
+            .expect("writing to a Vec doesn't fail");
+        String::from_utf8(writer).expect("labels are built from Rust strings, so they're UTF-8")
+    }
+
+    /// [`OperationGraph::to_graphviz`], straight into `writer`
+    pub fn write_graphviz(&'b self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        dot::render(self, &mut writer)
+    }
+}
+
+impl OperationGraph<'_> {
+    /// the graph as JSON. Unlike [`Operation::as_json`], which nests every node's history inside
+    /// it, this is a flat list of nodes referring to their inputs by id, so shared nodes only
+    /// show up once. `outputs` maps each output name to the id of its node, comparisons say
+    /// which `comparison` they used, selects which branch was `taken`, and choices their `rule`
+    /// along with the input they `picked` and by what `margin`. Custom operators get an
+    /// `operator` with their label, registered name, description and parameters, which is enough
+    /// for an [`crate::OperatorRegistry`] to rebuild the graph. Collapsed composites are marked
+    /// `collapsed`, and their inputs leave out the inner graph. Every node also gets
+    /// a `std_dev` if any of the sources are uncertain, and an `interval` (plus an
+    /// `interval_flag` if it got one) when built with [`GraphBuilder::intervals`], and
+    /// `abs_error`, `rel_error` and any `finding` when built with [`GraphBuilder::precision`]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.json_value())
+            .expect("a serde_json::Value always serializes")
+    }
+
+    /// [`OperationGraph::to_json`], straight into `writer`
+    pub fn write_json(&self, writer: impl std::io::Write) -> std::io::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.json_value())?;
+        Ok(())
+    }
+
+    fn json_value(&self) -> serde_json::Value {
+        use serde_json::{json, Map, Value};
+        let mut outputs = Map::new();
+        for (&idx, names) in &self.outputs {
+            for name in names {
+                outputs.insert(name.clone(), idx.into());
+            }
+        }
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .zip(&self.inputs)
+            .enumerate()
+            .map(|(id, (node, inputs))| {
+                let mut entry = json!({
+                    "id": id,
+                    "kind": node.op.kind_name(),
+                    "value": node.value(),
+                    "reason": node.reason,
+                    "inputs": inputs,
+                });
+                if self.collapsed[id] {
+                    entry["collapsed"] = true.into();
+                }
+                if let OperationType::Source {
+                    name: Some(name), ..
+                } = &node.op
+                {
+                    entry["name"] = name.as_ref().into();
+                }
+                match &node.op {
+                    OperationType::Compare { comparison, .. } => {
+                        entry["comparison"] = json!(comparison);
+                    }
+                    OperationType::Select { taken, .. } => entry["taken"] = json!(taken),
+                    OperationType::Choose {
+                        rule,
+                        picked,
+                        margin,
+                        ..
+                    } => {
+                        entry["rule"] = json!(rule);
+                        entry["picked"] = json!(picked);
+                        entry["margin"] = json!(margin);
+                    }
+                    OperationType::Other { op, .. } => {
+                        entry["operator"] = crate::operator_json(*op);
+                    }
+                    _ => {}
+                }
+                if let Some(std_devs) = &self.std_devs {
+                    entry["std_dev"] = std_devs[id].into();
+                }
+                if let Some(analysis) = &self.intervals {
+                    if let Some(interval) = analysis.get(node) {
+                        entry["interval"] = json!([interval.lo, interval.hi]);
+                    }
+                    if let Some(flag) = analysis.flag(node) {
+                        entry["interval_flag"] = json!(flag);
+                    }
+                }
+                if let Some(error) = self.precision.as_ref().and_then(|r| r.get(node)) {
+                    entry["abs_error"] = error.abs_error.into();
+                    entry["rel_error"] = error.rel_error.into();
+                    if let Some(finding) = error.finding {
+                        entry["finding"] = json!(finding);
+                    }
+                }
+                entry
+            })
+            .collect();
+        json!({ "outputs": outputs, "nodes": nodes })
     }
 }
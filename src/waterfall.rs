@@ -0,0 +1,249 @@
+//! Waterfall charts: how an output got from one scenario to another, one input at a time.
+//!
+//! The inputs are switched from their `before` values to their `after` values in the order
+//! given, and each step is however much the output moved when that input changed. The steps
+//! always add up to the total change, but with interactions between inputs (a product, say)
+//! how it gets split depends on the order.
+
+use serde_json::json;
+
+use crate::{attribution::source_label, attribution::Evaluator, Num, Operation};
+
+/// One changed input's step in a [`Waterfall`]
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'a> {
+    pub source: &'a Operation<'a>,
+    pub before: Num,
+    pub after: Num,
+    /// how much the output moved when this input changed
+    pub delta: Num,
+}
+
+/// An output's change between two scenarios, split into one step per changed input
+#[derive(Debug, Clone)]
+pub struct Waterfall<'a> {
+    pub root: &'a Operation<'a>,
+    /// the output in the `before` scenario
+    pub start: Num,
+    /// the output in the `after` scenario
+    pub end: Num,
+    pub steps: Vec<Step<'a>>,
+}
+
+const BAR_WIDTH: f32 = 72.;
+const BAR_GAP: f32 = 16.;
+const PLOT_HEIGHT: f32 = 240.;
+const MARGIN: f32 = 48.;
+const INCREASE: &str = "#4c9a2a";
+const DECREASE: &str = "#c0392b";
+const TOTAL: &str = "#7f8c8d";
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl<'a> Waterfall<'a> {
+    /// bars in drawing order: (label, bottom of the bar, top of the bar, color)
+    fn bars(&self) -> Vec<(String, Num, Num, &'static str)> {
+        let mut bars = vec![("start".to_string(), 0., self.start, TOTAL)];
+        let mut running = self.start;
+        for step in &self.steps {
+            let color = if step.delta >= 0. { INCREASE } else { DECREASE };
+            bars.push((
+                source_label(step.source),
+                running,
+                running + step.delta,
+                color,
+            ));
+            running += step.delta;
+        }
+        bars.push(("end".to_string(), 0., self.end, TOTAL));
+        bars
+    }
+
+    /// a [Vega-Lite](https://vega.github.io/vega-lite/) spec for the chart, with the data inlined
+    pub fn to_vega_lite(&self) -> String {
+        let values: Vec<_> = self
+            .bars()
+            .into_iter()
+            .enumerate()
+            .map(|(order, (label, from, to, color))| {
+                let kind = match color {
+                    INCREASE => "increase",
+                    DECREASE => "decrease",
+                    _ => "total",
+                };
+                json!({
+                    "order": order,
+                    "label": label,
+                    "from": from,
+                    "to": to,
+                    "amount": to - from,
+                    "kind": kind,
+                })
+            })
+            .collect();
+        let spec = json!({
+            "$schema": "https://vega.github.io/schema/vega-lite/v5.json",
+            "description": format!(
+                "{} from {} to {}",
+                self.root.reason.as_deref().unwrap_or("output"),
+                self.start,
+                self.end
+            ),
+            "data": { "values": values },
+            "encoding": {
+                "x": { "field": "label", "type": "ordinal", "sort": null, "title": null },
+            },
+            "layer": [
+                {
+                    "mark": { "type": "bar", "width": { "band": 0.8 } },
+                    "encoding": {
+                        "y": { "field": "from", "type": "quantitative", "title": null },
+                        "y2": { "field": "to" },
+                        "color": {
+                            "field": "kind",
+                            "type": "nominal",
+                            "scale": {
+                                "domain": ["increase", "decrease", "total"],
+                                "range": [INCREASE, DECREASE, TOTAL],
+                            },
+                            "legend": null,
+                        },
+                    },
+                },
+                {
+                    "mark": { "type": "text", "dy": -6 },
+                    "encoding": {
+                        "y": { "field": "to", "type": "quantitative" },
+                        "text": { "field": "amount", "type": "quantitative" },
+                    },
+                },
+            ],
+        });
+        serde_json::to_string_pretty(&spec).expect("a serde_json::Value always serializes")
+    }
+
+    /// the chart as a standalone SVG document
+    pub fn to_svg(&self) -> String {
+        let bars = self.bars();
+        let (low, high) = bars
+            .iter()
+            .flat_map(|&(_, from, to, _)| [from, to])
+            .fold((0 as Num, 0 as Num), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        let span = if high > low { high - low } else { 1. };
+        let y = |v: Num| MARGIN + (high - v) / span * PLOT_HEIGHT;
+        let width = 2. * MARGIN + bars.len() as f32 * (BAR_WIDTH + BAR_GAP);
+        let height = 2. * MARGIN + PLOT_HEIGHT;
+
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" font-size="11">"#
+        );
+        svg.push('\n');
+        svg += &format!(
+            r#"<line x1="{MARGIN}" y1="{zero}" x2="{end}" y2="{zero}" stroke="black"/>"#,
+            zero = y(0.),
+            end = width - MARGIN,
+        );
+        svg.push('\n');
+        for (idx, (label, from, to, color)) in bars.iter().enumerate() {
+            let x = MARGIN + idx as f32 * (BAR_WIDTH + BAR_GAP) + BAR_GAP / 2.;
+            let (top, bottom) = (y(from.max(*to)), y(from.min(*to)));
+            let center = x + BAR_WIDTH / 2.;
+            let amount = to - from;
+            svg += &format!(
+                r#"<rect x="{x}" y="{top}" width="{BAR_WIDTH}" height="{h}" fill="{color}"><title>{title}: {amount}</title></rect>"#,
+                h = (bottom - top).max(1.),
+                title = escape_xml(label),
+            );
+            svg.push('\n');
+            svg += &format!(
+                r#"<text x="{center}" y="{ty}" text-anchor="middle">{amount}</text>"#,
+                ty = top - 4.,
+            );
+            svg.push('\n');
+            svg += &format!(
+                r#"<text x="{center}" y="{ly}" text-anchor="middle">{label}</text>"#,
+                ly = height - MARGIN / 2.,
+                label = escape_xml(label),
+            );
+            svg.push('\n');
+            // connect each bar's running total to the next bar
+            if idx + 1 < bars.len() {
+                svg += &format!(
+                    r#"<line x1="{x1}" y1="{ry}" x2="{x2}" y2="{ry}" stroke="gray" stroke-dasharray="3,3"/>"#,
+                    x1 = x + BAR_WIDTH,
+                    x2 = x + BAR_WIDTH + BAR_GAP,
+                    ry = y(*to),
+                );
+                svg.push('\n');
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+impl<'a> Operation<'a> {
+    /// splits the change in this output between two scenarios into one step per input,
+    /// switching the inputs from `before` to `after` in the order given. Inputs that are the
+    /// same in both scenarios are left out. The graph is put back the way it was afterwards
+    /// ```
+    ///# use explainability_rs::{Operation, OpArena};
+    /// let arena = OpArena::new();
+    /// let (_, op_r) = Operation::make_ctors(&arena);
+    /// let price = op_r(10., "price");
+    /// let units = op_r(100., "units");
+    /// let revenue = price * (units, "revenue");
+    /// let waterfall = revenue.waterfall(&[price, units], &[10., 100.], &[12., 90.]);
+    /// assert_eq!(waterfall.steps[0].delta, 200.);
+    /// assert_eq!(waterfall.steps[1].delta, -120.);
+    /// let svg = waterfall.to_svg();
+    /// assert!(svg.starts_with("<svg") && svg.contains(">price</text>"));
+    /// ```
+    ///
+    /// # Panics
+    /// if `before` or `after` isn't the same length as `inputs`
+    pub fn waterfall(
+        &'a self,
+        inputs: &[&'a Operation<'a>],
+        before: &[Num],
+        after: &[Num],
+    ) -> Waterfall<'a> {
+        assert_eq!(
+            inputs.len(),
+            before.len(),
+            "need one before value per input"
+        );
+        assert_eq!(inputs.len(), after.len(), "need one after value per input");
+        let evaluator = Evaluator::new(self, inputs);
+        let mut values = before.to_vec();
+        let start = evaluator.evaluate(&values);
+        let mut previous = start;
+        let mut steps = vec![];
+        for (idx, &source) in inputs.iter().enumerate() {
+            if before[idx] == after[idx] {
+                continue;
+            }
+            values[idx] = after[idx];
+            let output = evaluator.evaluate(&values);
+            steps.push(Step {
+                source,
+                before: before[idx],
+                after: after[idx],
+                delta: output - previous,
+            });
+            previous = output;
+        }
+        drop(evaluator);
+        Waterfall {
+            root: self,
+            start,
+            end: previous,
+            steps,
+        }
+    }
+}